@@ -0,0 +1,149 @@
+use std::{convert::TryFrom, str::FromStr};
+
+use super::{
+    runtime_error::{ErrorKind, RuntimeError},
+    value::Value,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arithmetic {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    IntDivide,
+    Power,
+}
+
+// What happens when an integer result doesn't fit in 64 bits: a runtime error,
+// two's complement wrapping, or promotion to a float.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Overflow {
+    #[default]
+    Error,
+    Wrap,
+    Promote,
+}
+
+impl FromStr for Overflow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(Overflow::Error),
+            "wrap" => Ok(Overflow::Wrap),
+            "promote" => Ok(Overflow::Promote),
+            _ => Err(format!("Unknown overflow mode '{}'.", s)),
+        }
+    }
+}
+
+// Applies the operator to two numbers, or returns `None` when either operand
+// isn't one. Two integers give an integer, except that `/` always divides
+// exactly and a negative power is a fraction. Mixing an integer with a float
+// gives a float. Dividing by zero, including raising zero to a negative power,
+// is an error rather than an infinity or NaN.
+pub fn apply(
+    op: Arithmetic,
+    left: &Value,
+    right: &Value,
+    overflow: Overflow,
+) -> Option<Result<Value, RuntimeError>> {
+    match (left, right) {
+        (Value::Int(a), Value::Int(b)) => Some(integer(op, *a, *b, overflow)),
+        _ => Some(float(op, left.as_float()?, right.as_float()?)),
+    }
+}
+
+pub fn negate(n: i64, overflow: Overflow) -> Result<Value, RuntimeError> {
+    match n.checked_neg() {
+        Some(n) => Ok(Value::Int(n)),
+        None => match overflow {
+            Overflow::Error => Err(overflow_error()),
+            Overflow::Wrap => Ok(Value::Int(n.wrapping_neg())),
+            Overflow::Promote => Ok(Value::Number(-(n as f64))),
+        },
+    }
+}
+
+fn integer(op: Arithmetic, a: i64, b: i64, overflow: Overflow) -> Result<Value, RuntimeError> {
+    let checked = match op {
+        Arithmetic::Divide => return float(op, a as f64, b as f64),
+        Arithmetic::Power if b < 0 => return float(op, a as f64, b as f64),
+        Arithmetic::Modulo | Arithmetic::IntDivide if b == 0 => return Err(division_by_zero()),
+        Arithmetic::Add => a.checked_add(b),
+        Arithmetic::Subtract => a.checked_sub(b),
+        Arithmetic::Multiply => a.checked_mul(b),
+        // The remainder always fits, even for `i64::MIN % -1`.
+        Arithmetic::Modulo => Some(a.wrapping_rem(b)),
+        Arithmetic::IntDivide => a.checked_div(b),
+        Arithmetic::Power => checked_pow(a, b),
+    };
+
+    if let Some(n) = checked {
+        return Ok(Value::Int(n));
+    }
+
+    match overflow {
+        Overflow::Error => Err(overflow_error()),
+        Overflow::Wrap => Ok(Value::Int(match op {
+            Arithmetic::Add => a.wrapping_add(b),
+            Arithmetic::Subtract => a.wrapping_sub(b),
+            Arithmetic::Multiply => a.wrapping_mul(b),
+            Arithmetic::IntDivide => a.wrapping_div(b),
+            _ => wrapping_pow(a, b),
+        })),
+        Overflow::Promote => float(op, a as f64, b as f64),
+    }
+}
+
+fn float(op: Arithmetic, a: f64, b: f64) -> Result<Value, RuntimeError> {
+    let n = match op {
+        Arithmetic::Add => a + b,
+        Arithmetic::Subtract => a - b,
+        Arithmetic::Multiply => a * b,
+        Arithmetic::Divide | Arithmetic::Modulo | Arithmetic::IntDivide if b == 0.0 => {
+            return Err(division_by_zero())
+        }
+        Arithmetic::Divide => a / b,
+        Arithmetic::Modulo => a % b,
+        Arithmetic::IntDivide => (a / b).trunc(),
+        // A negative power is one divided by the positive power.
+        Arithmetic::Power if a == 0.0 && b < 0.0 => return Err(division_by_zero()),
+        Arithmetic::Power => a.powf(b),
+    };
+    Ok(Value::Number(n))
+}
+
+// Exponents too large for `checked_pow` only fit for bases of 0, 1 and -1.
+fn checked_pow(base: i64, exponent: i64) -> Option<i64> {
+    match u32::try_from(exponent) {
+        Ok(exponent) => base.checked_pow(exponent),
+        Err(_) => match base {
+            0 | 1 => Some(base),
+            -1 => Some(if exponent % 2 == 0 { 1 } else { -1 }),
+            _ => None,
+        },
+    }
+}
+
+fn wrapping_pow(mut base: i64, mut exponent: i64) -> i64 {
+    let mut result: i64 = 1;
+    while exponent > 0 {
+        if exponent % 2 == 1 {
+            result = result.wrapping_mul(base);
+        }
+        base = base.wrapping_mul(base);
+        exponent /= 2;
+    }
+    result
+}
+
+fn overflow_error() -> RuntimeError {
+    RuntimeError::new(ErrorKind::Overflow, "Integer overflow.")
+}
+
+fn division_by_zero() -> RuntimeError {
+    RuntimeError::new(ErrorKind::Value, "Division by zero.")
+}
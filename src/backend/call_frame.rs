@@ -1,17 +1,52 @@
-use super::function_obj::FunctionObj;
+use std::rc::Rc;
+
+use super::{closure_obj::ClosureObj, generator_obj::GeneratorObj};
 
 pub struct CallFrame {
-    pub function: FunctionObj,
+    pub closure: Rc<ClosureObj>,
     pub ip: usize,
+    // The stack index of the first argument. The callee itself sits just below it.
     pub slots_start: usize,
+    // Which of the function's positional parameters were passed an argument,
+    // kept only when some of them have default values.
+    pub given: Vec<bool>,
+    // The `try` blocks running in this call, innermost last.
+    pub handlers: Vec<Handler>,
+    // Whether the frame runs the top level of a module, which returns the
+    // module itself once it is done.
+    pub module: bool,
+    // The generator the frame runs the body of, which yielding suspends.
+    pub generator: Option<Rc<GeneratorObj>>,
 }
 
 impl CallFrame {
-    pub fn new(function: FunctionObj, ip: usize, slots_start: usize) -> CallFrame {
+    pub fn new(
+        closure: Rc<ClosureObj>,
+        ip: usize,
+        slots_start: usize,
+        given: Vec<bool>,
+    ) -> CallFrame {
         CallFrame {
-            function,
+            closure,
             ip,
             slots_start,
+            given,
+            handlers: vec![],
+            module: false,
+            generator: None,
         }
     }
 }
+
+// Where to go when an error reaches a `try` block, and how tall the stack was
+// when the block began.
+pub struct Handler {
+    pub ip: usize,
+    pub stack_len: usize,
+}
+
+impl Handler {
+    pub fn new(ip: usize, stack_len: usize) -> Handler {
+        Handler { ip, stack_len }
+    }
+}
@@ -0,0 +1,31 @@
+use std::{cell::RefCell, collections::VecDeque, fmt, rc::Rc};
+
+use super::{fiber_obj::FiberObj, value::Value};
+
+// A queue fibers pass values through. Sending never waits, and receiving from
+// an empty channel blocks the fiber until a value is sent.
+#[derive(Debug, Default)]
+pub struct ChannelObj {
+    pub buffer: RefCell<VecDeque<Value>>,
+    // The fibers blocked receiving, in the order they started waiting.
+    pub receivers: RefCell<VecDeque<Rc<FiberObj>>>,
+}
+
+impl ChannelObj {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+// Channels are told apart by identity.
+impl PartialEq for ChannelObj {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Display for ChannelObj {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<channel>")
+    }
+}
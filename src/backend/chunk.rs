@@ -6,7 +6,7 @@ use super::{op_code::OpCode, value::Value, value_array::ValueArray};
 pub struct Chunk {
     pub code: Vec<OpCode>,
     pub constants: Rc<RefCell<ValueArray>>,
-    lines: Vec<usize>,
+    lines: Vec<(usize, usize)>,
 }
 
 impl Chunk {
@@ -58,30 +58,50 @@ impl Chunk {
             OpCode::Constant(index) => {
                 println!("{} '{}'", instruction, self.constants.borrow().get(*index))
             }
+            OpCode::CallNamed(_, index)
+            | OpCode::DefGlobal(index)
+            | OpCode::GetGlobal(index)
+            | OpCode::SetGlobal(index)
+            | OpCode::Import(index)
+            | OpCode::ImportName(index)
+            | OpCode::GetProperty(index) => {
+                println!("{} '{}'", instruction, self.constants.borrow().get(*index))
+            }
+            OpCode::Closure(index) => {
+                let function = self.constants.borrow().get(*index);
+                println!("{} '{}'", instruction, function);
+                if let Value::Function(function) = function {
+                    for capture in function.captures.iter() {
+                        let kind = if capture.is_local { "local" } else { "upvalue" };
+                        println!("{:04}     |   {} {}", offset, kind, capture.index);
+                    }
+                }
+            }
             _ => println!("{}", instruction),
         };
     }
 
+    // Lines are run-length encoded as (line, number of instructions on that line).
     fn write_line(&mut self, line: usize) {
-        let current_line = self.lines.len();
-        if current_line == line {
-            self.lines[current_line - 1] += 1;
-        } else {
-            self.lines.push(1);
+        match self.lines.last_mut() {
+            Some((last_line, count)) if *last_line == line => *count += 1,
+            _ => self.lines.push((line, 1)),
         }
     }
 
     pub fn get_line(&self, offset: usize) -> usize {
-        let mut line_counter = self.lines[0];
-        let mut current_index = 1;
-        while line_counter <= offset && current_index < self.lines.len() {
-            line_counter += self.lines[current_index];
-            current_index += 1;
+        let mut line_counter = 0;
+        for (line, count) in self.lines.iter() {
+            line_counter += count;
+            if offset < line_counter {
+                return *line;
+            }
         }
-        current_index
+
+        self.lines.last().map_or(0, |(line, _)| *line)
     }
 
     pub fn count(&self) -> usize {
-        self.code.len() - 1
+        self.code.len()
     }
 }
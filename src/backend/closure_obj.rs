@@ -0,0 +1,32 @@
+use std::{cell::RefCell, fmt, rc::Rc};
+
+use super::{function_obj::FunctionObj, module_obj::ModuleObj, upvalue_obj::UpvalueObj};
+
+// A function together with the variables it captured from the functions
+// around it when it was created, and the module whose globals it sees.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClosureObj {
+    pub function: Rc<FunctionObj>,
+    pub upvalues: Vec<Rc<RefCell<UpvalueObj>>>,
+    pub module: Rc<ModuleObj>,
+}
+
+impl ClosureObj {
+    pub fn new(
+        function: Rc<FunctionObj>,
+        upvalues: Vec<Rc<RefCell<UpvalueObj>>>,
+        module: Rc<ModuleObj>,
+    ) -> Self {
+        Self {
+            function,
+            upvalues,
+            module,
+        }
+    }
+}
+
+impl fmt::Display for ClosureObj {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.function)
+    }
+}
@@ -0,0 +1,31 @@
+use std::fmt;
+
+// A `///` comment together with the declaration it documents.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DocComment {
+    pub keyword: String,
+    pub name: String,
+    pub line: usize,
+    pub text: String,
+}
+
+impl DocComment {
+    pub fn new(keyword: String, name: String, line: usize, text: String) -> Self {
+        Self {
+            keyword,
+            name,
+            line,
+            text,
+        }
+    }
+}
+
+impl fmt::Display for DocComment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} [line {}]", self.keyword, self.name, self.line)?;
+        for line in self.text.lines() {
+            write!(f, "\n    {}", line)?;
+        }
+        Ok(())
+    }
+}
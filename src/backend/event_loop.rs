@@ -0,0 +1,330 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    io::{self, ErrorKind as IoErrorKind, Read, Write},
+    net::{Ipv4Addr, Shutdown, TcpListener, TcpStream},
+    rc::Rc,
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::{
+    handle_obj::{HandleKind, HandleObj},
+    value::Value,
+};
+
+// How long the loop sleeps when nothing is ready and no timer is due sooner.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+const READ_SIZE: usize = 4096;
+
+// A callback the loop is ready to call, and the arguments to call it with.
+pub type Event = (Value, Vec<Value>);
+
+// The timers, file reads and loopback sockets a script is waiting on. The VM
+// runs the loop once the script is done, calling back as each piece of work
+// completes, until nothing is left pending.
+#[derive(Default)]
+pub struct EventLoop {
+    next_id: usize,
+    timers: Vec<Timer>,
+    file_reads: Vec<Pending<io::Result<String>>>,
+    connects: Vec<Pending<io::Result<TcpStream>>>,
+    servers: HashMap<usize, Server>,
+    sockets: HashMap<usize, Socket>,
+    ready: VecDeque<Event>,
+}
+
+struct Timer {
+    id: usize,
+    due: Instant,
+    callback: Value,
+}
+
+// Work done on another thread, whose result arrives through the receiver.
+struct Pending<T> {
+    receiver: Receiver<T>,
+    callback: Value,
+}
+
+struct Server {
+    listener: TcpListener,
+    callback: Value,
+}
+
+struct Socket {
+    stream: TcpStream,
+    // Text written but not yet sent.
+    output: Vec<u8>,
+    // Callbacks waiting for data, called in turn as it arrives.
+    reads: VecDeque<Value>,
+    // Whether to shut the socket down once its output is sent.
+    closing: bool,
+}
+
+impl EventLoop {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Whether any work could still call back.
+    pub fn is_pending(&self) -> bool {
+        !self.ready.is_empty()
+            || !self.timers.is_empty()
+            || !self.file_reads.is_empty()
+            || !self.connects.is_empty()
+            || !self.servers.is_empty()
+            || self
+                .sockets
+                .values()
+                .any(|socket| !socket.reads.is_empty() || !socket.output.is_empty())
+    }
+
+    // Starts a timer, unless the delay is too long for the time it's due to be
+    // represented.
+    pub fn set_timeout(&mut self, callback: Value, delay: Duration) -> Option<usize> {
+        let due = Instant::now().checked_add(delay)?;
+        let id = self.next_id();
+        self.timers.push(Timer { id, due, callback });
+        Some(id)
+    }
+
+    // Cancels the timer and returns whether it was still waiting.
+    pub fn clear_timeout(&mut self, id: usize) -> bool {
+        let count = self.timers.len();
+        self.timers.retain(|timer| timer.id != id);
+        self.timers.len() < count
+    }
+
+    pub fn read_file(&mut self, path: String, callback: Value) {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || sender.send(fs::read_to_string(path)));
+        self.file_reads.push(Pending { receiver, callback });
+    }
+
+    // Listens on the loopback address. Port 0 picks a free port.
+    pub fn listen(&mut self, port: u16, callback: Value) -> io::Result<Rc<HandleObj>> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        listener.set_nonblocking(true)?;
+        let id = self.next_id();
+        self.servers.insert(id, Server { listener, callback });
+        Ok(Rc::new(HandleObj::new(id, HandleKind::Server)))
+    }
+
+    pub fn connect(&mut self, port: u16, callback: Value) {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || sender.send(TcpStream::connect((Ipv4Addr::LOCALHOST, port))));
+        self.connects.push(Pending { receiver, callback });
+    }
+
+    pub fn local_port(&self, handle: &HandleObj) -> Result<u16, String> {
+        let server = self.servers.get(&handle.id).ok_or("Server is closed.")?;
+        let address = server.listener.local_addr().map_err(|e| e.to_string())?;
+        Ok(address.port())
+    }
+
+    pub fn write(&mut self, handle: &HandleObj, text: &str) -> Result<(), String> {
+        let socket = self.open_socket(handle)?;
+        socket.output.extend_from_slice(text.as_bytes());
+        Ok(())
+    }
+
+    // Asks for the next data to arrive on the socket. The callback gets it as a
+    // string, or nil once the other end has closed.
+    pub fn read(&mut self, handle: &HandleObj, callback: Value) -> Result<(), String> {
+        self.open_socket(handle)?.reads.push_back(callback);
+        Ok(())
+    }
+
+    // Stops a server accepting connections, or shuts a socket down once what
+    // was written to it has been sent.
+    pub fn close(&mut self, handle: &HandleObj) {
+        match handle.kind {
+            HandleKind::Server => {
+                self.servers.remove(&handle.id);
+            }
+            HandleKind::Socket => {
+                if let Some(socket) = self.sockets.get_mut(&handle.id) {
+                    socket.closing = true;
+                    socket.reads.clear();
+                }
+            }
+        }
+    }
+
+    // Waits for the next callback to be ready, or returns `None` once nothing
+    // is pending.
+    pub fn next_event(&mut self) -> Option<Event> {
+        loop {
+            if let Some(event) = self.ready.pop_front() {
+                return Some(event);
+            }
+
+            if !self.is_pending() {
+                return None;
+            }
+
+            self.poll();
+            if self.ready.is_empty() {
+                thread::sleep(self.wait_time());
+            }
+        }
+    }
+
+    fn poll(&mut self) {
+        self.poll_timers();
+        self.poll_file_reads();
+        self.poll_connects();
+        self.poll_servers();
+        self.poll_sockets();
+    }
+
+    // Timers that are due fire in the order they are due, and timers due at the
+    // same time in the order they were set.
+    fn poll_timers(&mut self) {
+        let now = Instant::now();
+        let (mut due, waiting) = self.timers.drain(..).partition(|timer| timer.due <= now);
+        self.timers = waiting;
+        due.sort_by_key(|timer: &Timer| (timer.due, timer.id));
+        for timer in due {
+            self.ready.push_back((timer.callback, vec![]));
+        }
+    }
+
+    fn poll_file_reads(&mut self) {
+        for (callback, result) in take_finished(&mut self.file_reads) {
+            let args = match result {
+                Ok(text) => vec![Value::Nil, Value::Str(text)],
+                Err(e) => vec![Value::Str(e.to_string()), Value::Nil],
+            };
+            self.ready.push_back((callback, args));
+        }
+    }
+
+    fn poll_connects(&mut self) {
+        for (callback, result) in take_finished(&mut self.connects) {
+            let args = match result.and_then(|stream| self.add_socket(stream)) {
+                Ok(handle) => vec![Value::Nil, Value::Handle(handle)],
+                Err(e) => vec![Value::Str(e.to_string()), Value::Nil],
+            };
+            self.ready.push_back((callback, args));
+        }
+    }
+
+    fn poll_servers(&mut self) {
+        let mut accepted = vec![];
+        for server in self.servers.values() {
+            while let Ok((stream, _)) = server.listener.accept() {
+                accepted.push((server.callback.clone(), stream));
+            }
+        }
+
+        for (callback, stream) in accepted {
+            if let Ok(handle) = self.add_socket(stream) {
+                self.ready
+                    .push_back((callback, vec![Value::Handle(handle)]));
+            }
+        }
+    }
+
+    fn poll_sockets(&mut self) {
+        let mut closed = vec![];
+        for (id, socket) in self.sockets.iter_mut() {
+            socket.flush();
+            if socket.closing && socket.output.is_empty() {
+                let _ = socket.stream.shutdown(Shutdown::Both);
+                closed.push(*id);
+                continue;
+            }
+
+            if socket.reads.is_empty() {
+                continue;
+            }
+
+            let mut buffer = [0; READ_SIZE];
+            let data = match socket.stream.read(&mut buffer) {
+                Ok(0) => Value::Nil,
+                Ok(count) => Value::Str(String::from_utf8_lossy(&buffer[..count]).into_owned()),
+                Err(e) if e.kind() == IoErrorKind::WouldBlock => continue,
+                Err(_) => Value::Nil,
+            };
+            if let Some(callback) = socket.reads.pop_front() {
+                self.ready.push_back((callback, vec![data]));
+            }
+        }
+
+        for id in closed {
+            self.sockets.remove(&id);
+        }
+    }
+
+    fn add_socket(&mut self, stream: TcpStream) -> io::Result<Rc<HandleObj>> {
+        stream.set_nonblocking(true)?;
+        let id = self.next_id();
+        let handle = Rc::new(HandleObj::new(id, HandleKind::Socket));
+        let socket = Socket {
+            stream,
+            output: vec![],
+            reads: VecDeque::new(),
+            closing: false,
+        };
+        self.sockets.insert(id, socket);
+        Ok(handle)
+    }
+
+    fn open_socket(&mut self, handle: &HandleObj) -> Result<&mut Socket, String> {
+        match self.sockets.get_mut(&handle.id) {
+            Some(socket) if handle.kind == HandleKind::Socket && !socket.closing => Ok(socket),
+            _ => Err(String::from("Socket is closed.")),
+        }
+    }
+
+    // Sleeps no longer than until the next timer is due.
+    fn wait_time(&self) -> Duration {
+        let now = Instant::now();
+        self.timers
+            .iter()
+            .map(|timer| timer.due.saturating_duration_since(now))
+            .min()
+            .map_or(POLL_INTERVAL, |due| due.min(POLL_INTERVAL))
+    }
+
+    fn next_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id
+    }
+}
+
+impl Socket {
+    // Sends as much of the output as the socket takes without blocking.
+    fn flush(&mut self) {
+        while !self.output.is_empty() {
+            match self.stream.write(&self.output) {
+                Ok(0) => break,
+                Ok(count) => {
+                    self.output.drain(..count);
+                }
+                Err(e) if e.kind() == IoErrorKind::WouldBlock => break,
+                Err(_) => {
+                    self.output.clear();
+                    self.closing = true;
+                }
+            }
+        }
+    }
+}
+
+// Removes the work other threads have finished, with the callbacks waiting on
+// it. Work whose thread died without a result is dropped.
+fn take_finished<T>(pending: &mut Vec<Pending<T>>) -> Vec<(Value, T)> {
+    let mut finished = vec![];
+    pending.retain(|work| match work.receiver.try_recv() {
+        Ok(result) => {
+            finished.push((work.callback.clone(), result));
+            false
+        }
+        Err(TryRecvError::Empty) => true,
+        Err(TryRecvError::Disconnected) => false,
+    });
+    finished
+}
@@ -0,0 +1,76 @@
+use std::{
+    cell::{Cell, RefCell},
+    fmt,
+};
+
+use super::{saved_stack::SavedStack, value::Value};
+
+// A thread of execution with its own value and frame stacks. Fibers take
+// turns on the VM, switching when the running one yields or has to wait on a
+// channel.
+pub struct FiberObj {
+    // The function the fiber was spawned with, or empty for the main script.
+    pub name: String,
+    pub state: Cell<FiberState>,
+    pub saved: RefCell<SavedStack>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FiberState {
+    Ready,
+    Running,
+    Blocked,
+    Done,
+}
+
+impl FiberObj {
+    pub fn new(name: String, stack: SavedStack) -> Self {
+        Self {
+            name,
+            state: Cell::new(FiberState::Ready),
+            saved: RefCell::new(stack),
+        }
+    }
+
+    // The fiber running the script itself, whose stacks are the VM's until it
+    // switches away.
+    pub fn main() -> Self {
+        Self {
+            name: String::new(),
+            state: Cell::new(FiberState::Running),
+            saved: RefCell::new(SavedStack::new(vec![], vec![])),
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.state.get() == FiberState::Done
+    }
+
+    // Hands a value to the fiber as the result of the call it stopped in.
+    pub fn push(&self, value: Value) {
+        self.saved.borrow().values.borrow_mut().push(value);
+    }
+}
+
+impl fmt::Debug for FiberObj {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "FiberObj({:?})", self.state.get())
+    }
+}
+
+// Fibers are told apart by identity, since each spawn makes a new one.
+impl PartialEq for FiberObj {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Display for FiberObj {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.name.is_empty() {
+            write!(f, "<fiber script>")
+        } else {
+            write!(f, "<fiber {}>", self.name)
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use super::value::Value;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+// The `:spec` part of an interpolation such as `"${price:>8.2}"`, written as
+// `[[fill]align][0][width][.precision]`. Precision is the number of decimals for
+// numbers and the maximum number of characters for anything else.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatSpec {
+    fill: char,
+    align: Option<Align>,
+    zero_pad: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl FormatSpec {
+    pub fn parse(spec: &str) -> Option<FormatSpec> {
+        let chars: Vec<char> = spec.chars().collect();
+        let mut format = FormatSpec {
+            fill: ' ',
+            align: None,
+            zero_pad: false,
+            width: 0,
+            precision: None,
+        };
+
+        let mut i = 0;
+        if let Some(align) = chars.get(1).and_then(|c| Self::align(*c)) {
+            format.fill = chars[0];
+            format.align = Some(align);
+            i = 2;
+        } else if let Some(align) = chars.first().and_then(|c| Self::align(*c)) {
+            format.align = Some(align);
+            i = 1;
+        }
+
+        if chars.get(i) == Some(&'0') {
+            format.zero_pad = true;
+            i += 1;
+        }
+
+        let (width, next) = Self::digits(&chars, i);
+        format.width = width.unwrap_or(0);
+        i = next;
+
+        if chars.get(i) == Some(&'.') {
+            let (precision, next) = Self::digits(&chars, i + 1);
+            format.precision = Some(precision?);
+            i = next;
+        }
+
+        if i == chars.len() {
+            Some(format)
+        } else {
+            None
+        }
+    }
+
+    pub fn apply(&self, value: &Value) -> String {
+        let text = match (value, self.precision) {
+            (Value::Int(n), Some(precision)) => format!("{:.*}", precision, *n as f64),
+            (Value::Number(n), Some(precision)) => format!("{:.*}", precision, n),
+            (_, Some(precision)) => value.stringify().chars().take(precision).collect(),
+            _ => value.stringify(),
+        };
+
+        let length = text.chars().count();
+        if length >= self.width {
+            return text;
+        }
+
+        let padding = self.width - length;
+        if self.zero_pad && self.align.is_none() && value.is_number() {
+            // Zeros go between the sign and the digits.
+            let (sign, digits) = match text.strip_prefix('-') {
+                Some(digits) => ("-", digits),
+                None => ("", text.as_str()),
+            };
+            return format!("{}{}{}", sign, "0".repeat(padding), digits);
+        }
+
+        let fill = if self.zero_pad && self.align.is_none() {
+            '0'
+        } else {
+            self.fill
+        };
+        let align = self.align.unwrap_or(if value.is_number() {
+            Align::Right
+        } else {
+            Align::Left
+        });
+        let (before, after) = match align {
+            Align::Left => (0, padding),
+            Align::Right => (padding, 0),
+            Align::Center => (padding / 2, padding - padding / 2),
+        };
+
+        let mut padded = String::with_capacity(text.len() + padding);
+        padded.extend(std::iter::repeat_n(fill, before));
+        padded.push_str(&text);
+        padded.extend(std::iter::repeat_n(fill, after));
+        padded
+    }
+
+    fn align(c: char) -> Option<Align> {
+        match c {
+            '<' => Some(Align::Left),
+            '>' => Some(Align::Right),
+            '^' => Some(Align::Center),
+            _ => None,
+        }
+    }
+
+    fn digits(chars: &[char], start: usize) -> (Option<usize>, usize) {
+        let end = chars[start.min(chars.len())..]
+            .iter()
+            .position(|c| !c.is_ascii_digit())
+            .map_or(chars.len(), |length| start + length);
+        let digits: String = chars[start.min(end)..end].iter().collect();
+        (digits.parse().ok(), end)
+    }
+}
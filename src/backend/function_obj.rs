@@ -1,21 +1,44 @@
-use super::chunk::Chunk;
+use super::{chunk::Chunk, doc_comment::DocComment, parameters::Parameters, value::Value};
 use std::fmt;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct FunctionObj {
-    pub arity: u32,
+    pub params: Parameters,
     pub chunk: Chunk,
     pub name: String,
+    // Doc comments of the declarations made directly in this function's body.
+    pub docs: Vec<DocComment>,
+    // Where each of the closure's upvalues is captured from when it is created.
+    pub captures: Vec<Capture>,
+    // Whether the function yields, so that calling it makes a generator.
+    pub generator: bool,
 }
 
 impl FunctionObj {
     pub fn new(name: String) -> FunctionObj {
         FunctionObj {
-            arity: 0,
+            params: Parameters::default(),
             chunk: Chunk::new(),
-            name: name,
+            name,
+            docs: vec![],
+            captures: vec![],
+            generator: false,
         }
     }
+
+    // The doc comments of this function and of every function nested in it,
+    // in source order.
+    pub fn all_docs(&self) -> Vec<DocComment> {
+        let mut docs = self.docs.clone();
+        let constants = self.chunk.constants.borrow();
+        for i in 0..constants.count() {
+            if let Value::Function(function) = constants.get(i) {
+                docs.extend(function.all_docs());
+            }
+        }
+        docs.sort_by_key(|doc| doc.line);
+        docs
+    }
 }
 
 impl fmt::Display for FunctionObj {
@@ -28,6 +51,21 @@ impl fmt::Display for FunctionObj {
     }
 }
 
+// A variable captured by a closure: either a local of the function creating
+// it, or one of that function's own upvalues.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Capture {
+    pub is_local: bool,
+    pub index: usize,
+}
+
+impl Capture {
+    pub fn new(is_local: bool, index: usize) -> Self {
+        Self { is_local, index }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
 pub enum FunctionType {
     Function,
     Script,
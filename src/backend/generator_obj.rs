@@ -0,0 +1,58 @@
+use std::{
+    cell::{Cell, RefCell},
+    fmt,
+    rc::Rc,
+};
+
+use super::{
+    call_frame::CallFrame, closure_obj::ClosureObj, saved_stack::SavedStack, value::Value,
+};
+
+// A call to a generator function that runs a step at a time. While it is
+// suspended it keeps its own segment of the stack, with the generator function
+// and its arguments at the bottom, and the frames running on that segment.
+pub struct GeneratorObj {
+    pub closure: Rc<ClosureObj>,
+    pub state: Cell<GeneratorState>,
+    pub saved: RefCell<SavedStack>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GeneratorState {
+    Suspended,
+    Running,
+    Done,
+}
+
+impl GeneratorObj {
+    pub fn new(closure: Rc<ClosureObj>, stack: Vec<Value>, frame: CallFrame) -> Self {
+        Self {
+            closure,
+            state: Cell::new(GeneratorState::Suspended),
+            saved: RefCell::new(SavedStack::new(stack, vec![frame])),
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.state.get() == GeneratorState::Done
+    }
+}
+
+impl fmt::Debug for GeneratorObj {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "GeneratorObj({:?})", self.state.get())
+    }
+}
+
+// Generators are told apart by identity, since each call makes a new one.
+impl PartialEq for GeneratorObj {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Display for GeneratorObj {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<generator {}>", self.closure.function.name)
+    }
+}
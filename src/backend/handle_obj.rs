@@ -0,0 +1,35 @@
+use std::fmt;
+
+// A server or socket the event loop holds, as scripts see it.
+#[derive(Debug)]
+pub struct HandleObj {
+    pub id: usize,
+    pub kind: HandleKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HandleKind {
+    Server,
+    Socket,
+}
+
+impl HandleObj {
+    pub fn new(id: usize, kind: HandleKind) -> Self {
+        Self { id, kind }
+    }
+}
+
+impl PartialEq for HandleObj {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl fmt::Display for HandleObj {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            HandleKind::Server => write!(f, "<server {}>", self.id),
+            HandleKind::Socket => write!(f, "<socket {}>", self.id),
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use super::{
+    runtime_error::{ErrorKind, RuntimeError},
+    value::Value,
+};
+
+// The item after `cursor` in a list, map or string, and the cursor to resume
+// from, or `None` once the iterable is exhausted. Lists and maps are read as
+// they are at each step, so items pushed during the loop are visited too. A
+// map yields its keys in insertion order and a string its characters, with the
+// cursor a byte offset into it.
+pub fn next(iterable: &Value, cursor: usize) -> Result<Option<(Value, usize)>, RuntimeError> {
+    match iterable {
+        Value::List(items) => Ok(items
+            .borrow()
+            .get(cursor)
+            .map(|item| (item.clone(), cursor + 1))),
+        Value::Map(map) => Ok(map.borrow().key_at(cursor).map(|key| (key, cursor + 1))),
+        Value::Str(s) => Ok(next_char(s, cursor)),
+        Value::SourceStr(s) => Ok(next_char(s.as_str(), cursor)),
+        _ => Err(RuntimeError::new(
+            ErrorKind::Type,
+            "Can only iterate over lists, maps and strings.",
+        )),
+    }
+}
+
+// The number after `current` in a range, and the number to resume from, or
+// `None` once the range has passed its end. A negative step counts down. A nil
+// resume value marks an integer range that stepped past the last integer.
+pub fn next_in_range(
+    current: &Value,
+    end: &Value,
+    step: &Value,
+    inclusive: bool,
+) -> Result<Option<(Value, Value)>, RuntimeError> {
+    if let Value::Nil = current {
+        return Ok(None);
+    }
+
+    if let (Value::Int(current), Value::Int(end), Value::Int(step)) = (current, end, step) {
+        return next_in_int_range(*current, *end, *step, inclusive);
+    }
+
+    let (current, end, step) = match (current.as_float(), end.as_float(), step.as_float()) {
+        (Some(current), Some(end), Some(step)) => (current, end, step),
+        _ => {
+            return Err(RuntimeError::new(
+                ErrorKind::Type,
+                "Range bounds and step must be numbers.",
+            ))
+        }
+    };
+
+    if step == 0.0 {
+        return Err(RuntimeError::new(
+            ErrorKind::Value,
+            "Range step can't be zero.",
+        ));
+    }
+
+    let more = if step > 0.0 {
+        current < end || (inclusive && current == end)
+    } else {
+        current > end || (inclusive && current == end)
+    };
+
+    if more {
+        Ok(Some((
+            Value::Number(current),
+            Value::Number(current + step),
+        )))
+    } else {
+        Ok(None)
+    }
+}
+
+fn next_char(string: &str, offset: usize) -> Option<(Value, usize)> {
+    let c = string[offset..].chars().next()?;
+    Some((Value::Str(c.to_string()), offset + c.len_utf8()))
+}
+
+// Like a float range, except that stepping past the last integer resumes from
+// nil, which ends the range rather than overflowing.
+fn next_in_int_range(
+    current: i64,
+    end: i64,
+    step: i64,
+    inclusive: bool,
+) -> Result<Option<(Value, Value)>, RuntimeError> {
+    if step == 0 {
+        return Err(RuntimeError::new(
+            ErrorKind::Value,
+            "Range step can't be zero.",
+        ));
+    }
+
+    let more = if step > 0 {
+        current < end || (inclusive && current == end)
+    } else {
+        current > end || (inclusive && current == end)
+    };
+
+    if !more {
+        return Ok(None);
+    }
+
+    let next = match current.checked_add(step) {
+        Some(next) => Value::Int(next),
+        None => Value::Nil,
+    };
+    Ok(Some((Value::Int(current), next)))
+}
@@ -0,0 +1,123 @@
+use std::{collections::HashMap, fmt};
+
+use super::{
+    runtime_error::{ErrorKind, RuntimeError},
+    value::{as_integer, display_once, Value},
+};
+
+// A hash map that remembers the order its keys were first inserted in.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MapObj {
+    entries: Vec<(Value, Value)>,
+    positions: HashMap<MapKey, usize>,
+}
+
+// The hashable form of a key. Two keys are the same exactly when the values
+// are equal: strings by content wherever they are stored, and numbers by
+// value, so `0`, `0.0` and `-0` are one key.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum MapKey {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Number(u64),
+    Str(String),
+}
+
+impl MapKey {
+    fn new(value: &Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::Nil => Ok(MapKey::Nil),
+            Value::Bool(b) => Ok(MapKey::Bool(*b)),
+            Value::Int(n) => Ok(MapKey::Int(*n)),
+            Value::Number(n) if n.is_nan() => {
+                Err(RuntimeError::new(ErrorKind::Value, "Map key can't be NaN."))
+            }
+            // Whole floats share keys with integers, which also turns `-0` into `0`.
+            Value::Number(n) => Ok(match as_integer(*n) {
+                Some(n) => MapKey::Int(n),
+                None => MapKey::Number(n.to_bits()),
+            }),
+            _ => match value.as_string() {
+                Some(string) => Ok(MapKey::Str(string)),
+                None => Err(RuntimeError::new(
+                    ErrorKind::Type,
+                    "Map keys must be numbers, strings, booleans or nil.",
+                )),
+            },
+        }
+    }
+}
+
+impl MapObj {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn get(&self, key: &Value) -> Result<Option<&Value>, RuntimeError> {
+        let key = MapKey::new(key)?;
+        Ok(self.positions.get(&key).map(|&i| &self.entries[i].1))
+    }
+
+    // Setting a key that is already there keeps its place in the order.
+    pub fn insert(&mut self, key: Value, value: Value) -> Result<(), RuntimeError> {
+        let hashed = MapKey::new(&key)?;
+        match self.positions.get(&hashed) {
+            Some(&i) => self.entries[i].1 = value,
+            None => {
+                self.positions.insert(hashed, self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &Value) -> Result<Option<Value>, RuntimeError> {
+        let position = match self.positions.remove(&MapKey::new(key)?) {
+            Some(position) => position,
+            None => return Ok(None),
+        };
+
+        let (_, value) = self.entries.remove(position);
+        for i in self.positions.values_mut() {
+            if *i > position {
+                *i -= 1;
+            }
+        }
+        Ok(Some(value))
+    }
+
+    pub fn key_at(&self, index: usize) -> Option<Value> {
+        self.entries.get(index).map(|(key, _)| key.clone())
+    }
+
+    pub fn keys(&self) -> Vec<Value> {
+        self.entries.iter().map(|(key, _)| key.clone()).collect()
+    }
+
+    pub fn values(&self) -> Vec<Value> {
+        self.entries
+            .iter()
+            .map(|(_, value)| value.clone())
+            .collect()
+    }
+}
+
+impl fmt::Display for MapObj {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        display_once(f, self as *const Self as usize, "{...}", |f| {
+            write!(f, "{{")?;
+            for (index, (key, value)) in self.entries.iter().enumerate() {
+                if index > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}: {}", key, value)?;
+            }
+            write!(f, "}}")
+        })
+    }
+}
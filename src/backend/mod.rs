@@ -1,9 +1,28 @@
+pub mod arithmetic;
 pub mod call_frame;
+pub mod channel_obj;
 pub mod chunk;
+pub mod closure_obj;
+pub mod doc_comment;
+mod event_loop;
+pub mod fiber_obj;
+pub mod format_spec;
 pub mod function_obj;
+pub mod generator_obj;
+pub mod handle_obj;
+mod iteration;
+pub mod map_obj;
+pub mod module_obj;
+pub mod native_obj;
+mod natives;
 pub mod obj;
 pub mod op_code;
+pub mod parameters;
+pub mod runtime_error;
+pub mod saved_stack;
 pub mod source_str;
+mod subscript;
+pub mod upvalue_obj;
 pub mod value;
 mod value_array;
 pub mod vm;
@@ -0,0 +1,45 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    fmt,
+    path::PathBuf,
+};
+
+use super::value::Value;
+
+// A script file and the globals its top level defines. The globals of an
+// imported module are its namespace.
+#[derive(Debug)]
+pub struct ModuleObj {
+    // The path the module was imported by, as it is shown in errors.
+    pub name: String,
+    pub path: PathBuf,
+    pub globals: RefCell<HashMap<String, Value>>,
+    // Whether the top level has finished running. An import of a module that
+    // hasn't is an import cycle.
+    pub loaded: Cell<bool>,
+}
+
+impl ModuleObj {
+    pub fn new(name: String, path: PathBuf) -> Self {
+        Self {
+            name,
+            path,
+            globals: RefCell::new(HashMap::new()),
+            loaded: Cell::new(false),
+        }
+    }
+}
+
+// Modules are told apart by identity, since each file is loaded only once.
+impl PartialEq for ModuleObj {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Display for ModuleObj {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<module {}>", self.name)
+    }
+}
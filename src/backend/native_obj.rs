@@ -0,0 +1,55 @@
+use std::fmt;
+
+use super::{runtime_error::RuntimeError, value::Value, vm::Vm};
+
+pub type NativeFn = fn(&[Value]) -> Result<Value, RuntimeError>;
+
+// A native that drives the VM itself, like resuming a generator. It pushes its
+// result, or the frames that will leave a result in its place.
+pub type VmNativeFn = fn(&mut Vm, Vec<Value>) -> Result<(), RuntimeError>;
+
+#[derive(Debug, Clone, Copy)]
+pub enum NativeBody {
+    Value(NativeFn),
+    Vm(VmNativeFn),
+}
+
+// A function implemented in Rust and called with its arguments from the stack.
+#[derive(Debug, Clone)]
+pub struct NativeObj {
+    pub name: &'static str,
+    pub arity: usize,
+    pub function: NativeBody,
+}
+
+impl NativeObj {
+    pub fn new(name: &'static str, arity: usize, function: NativeFn) -> Self {
+        Self {
+            name,
+            arity,
+            function: NativeBody::Value(function),
+        }
+    }
+
+    pub fn with_vm(name: &'static str, arity: usize, function: VmNativeFn) -> Self {
+        Self {
+            name,
+            arity,
+            function: NativeBody::Vm(function),
+        }
+    }
+}
+
+// Natives are told apart by name, since function pointers can't be compared
+// reliably.
+impl PartialEq for NativeObj {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl fmt::Display for NativeObj {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
@@ -0,0 +1,366 @@
+use std::{
+    cell::RefCell,
+    convert::TryFrom,
+    rc::Rc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    channel_obj::ChannelObj,
+    generator_obj::GeneratorObj,
+    handle_obj::{HandleKind, HandleObj},
+    map_obj::MapObj,
+    native_obj::NativeObj,
+    runtime_error::{ErrorKind, RuntimeError},
+    subscript,
+    value::Value,
+    vm::Vm,
+};
+
+// The natives every script can call, and whether reference Lox has them too.
+pub fn natives() -> Vec<(NativeObj, bool)> {
+    vec![
+        (NativeObj::new("clock", 0, clock), true),
+        (NativeObj::new("len", 1, len), false),
+        (NativeObj::new("push", 2, push), false),
+        (NativeObj::new("pop", 1, pop), false),
+        (NativeObj::new("insert", 3, insert), false),
+        (NativeObj::new("remove", 2, remove), false),
+        (NativeObj::new("has", 2, has), false),
+        (NativeObj::new("delete", 2, delete), false),
+        (NativeObj::new("keys", 1, keys), false),
+        (NativeObj::new("values", 1, values), false),
+        (NativeObj::with_vm("next", 1, next), false),
+        (NativeObj::new("done", 1, done), false),
+        (NativeObj::with_vm("spawn", 1, spawn), false),
+        (NativeObj::new("channel", 0, channel), false),
+        (NativeObj::with_vm("send", 2, send), false),
+        (NativeObj::with_vm("recv", 1, recv), false),
+        (NativeObj::with_vm("setTimeout", 2, set_timeout), false),
+        (NativeObj::with_vm("clearTimeout", 1, clear_timeout), false),
+        (NativeObj::with_vm("readFile", 2, read_file), false),
+        (NativeObj::with_vm("listen", 2, listen), false),
+        (NativeObj::with_vm("connect", 2, connect), false),
+        (NativeObj::with_vm("port", 1, port), false),
+        (NativeObj::with_vm("write", 2, write), false),
+        (NativeObj::with_vm("read", 2, read), false),
+        (NativeObj::with_vm("close", 1, close), false),
+    ]
+}
+
+// Seconds since the Unix epoch.
+fn clock(_args: &[Value]) -> Result<Value, RuntimeError> {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| RuntimeError::new(ErrorKind::Internal, e.to_string()))?;
+    Ok(Value::Number(elapsed.as_secs_f64()))
+}
+
+// The number of items in a list or map, or of characters in a string.
+fn len(args: &[Value]) -> Result<Value, RuntimeError> {
+    let len = match &args[0] {
+        Value::List(items) => items.borrow().len(),
+        Value::Map(map) => map.borrow().len(),
+        value => match value.as_string() {
+            Some(string) => string.chars().count(),
+            None => {
+                return Err(RuntimeError::new(
+                    ErrorKind::Type,
+                    "Can only take the length of lists, maps and strings.",
+                ))
+            }
+        },
+    };
+
+    Ok(Value::Int(len as i64))
+}
+
+fn push(args: &[Value]) -> Result<Value, RuntimeError> {
+    list_arg(&args[0], "push")?
+        .borrow_mut()
+        .push(args[1].clone());
+    Ok(Value::Nil)
+}
+
+fn pop(args: &[Value]) -> Result<Value, RuntimeError> {
+    list_arg(&args[0], "pop")?
+        .borrow_mut()
+        .pop()
+        .ok_or_else(|| RuntimeError::new(ErrorKind::Index, "Can't pop from an empty list."))
+}
+
+// Inserts the value before the item at the index, or at the end of the list
+// when the index is its length.
+fn insert(args: &[Value]) -> Result<Value, RuntimeError> {
+    let items = list_arg(&args[0], "insert")?;
+    let position = subscript::insert_position(&args[1], items.borrow().len())?;
+    items.borrow_mut().insert(position, args[2].clone());
+    Ok(Value::Nil)
+}
+
+// Removes the item at the index and returns it.
+fn remove(args: &[Value]) -> Result<Value, RuntimeError> {
+    let items = list_arg(&args[0], "remove")?;
+    let removed = subscript::get(&args[0], &args[1])?;
+    let position = subscript::insert_position(&args[1], items.borrow().len())?;
+    items.borrow_mut().remove(position);
+    Ok(removed)
+}
+
+fn list_arg<'a>(value: &'a Value, name: &str) -> Result<&'a Rc<RefCell<Vec<Value>>>, RuntimeError> {
+    match value {
+        Value::List(items) => Ok(items),
+        _ => Err(RuntimeError::new(
+            ErrorKind::Type,
+            format!("First argument to '{}' must be a list.", name),
+        )),
+    }
+}
+
+fn has(args: &[Value]) -> Result<Value, RuntimeError> {
+    let found = map_arg(&args[0], "has")?.borrow().get(&args[1])?.is_some();
+    Ok(Value::Bool(found))
+}
+
+// Removes the key from the map and returns whether it was there.
+fn delete(args: &[Value]) -> Result<Value, RuntimeError> {
+    let removed = map_arg(&args[0], "delete")?.borrow_mut().remove(&args[1])?;
+    Ok(Value::Bool(removed.is_some()))
+}
+
+// The keys of the map in the order they were first inserted.
+fn keys(args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::list(map_arg(&args[0], "keys")?.borrow().keys()))
+}
+
+fn values(args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::list(map_arg(&args[0], "values")?.borrow().values()))
+}
+
+// Runs the generator until it yields the next value, which the call returns.
+// Once the generator returns instead, the call returns that value.
+fn next(vm: &mut Vm, args: Vec<Value>) -> Result<(), RuntimeError> {
+    let generator = generator_arg(&args[0], "next")?;
+    vm.resume(Rc::clone(generator))
+}
+
+// Whether the generator or fiber has returned, or stopped on an error.
+fn done(args: &[Value]) -> Result<Value, RuntimeError> {
+    match &args[0] {
+        Value::Generator(generator) => Ok(Value::Bool(generator.is_done())),
+        Value::Fiber(fiber) => Ok(Value::Bool(fiber.is_done())),
+        _ => Err(RuntimeError::new(
+            ErrorKind::Type,
+            "First argument to 'done' must be a generator or a fiber.",
+        )),
+    }
+}
+
+fn generator_arg<'a>(value: &'a Value, name: &str) -> Result<&'a Rc<GeneratorObj>, RuntimeError> {
+    match value {
+        Value::Generator(generator) => Ok(generator),
+        _ => Err(RuntimeError::new(
+            ErrorKind::Type,
+            format!("First argument to '{}' must be a generator.", name),
+        )),
+    }
+}
+
+// Runs the function in a new fiber, which starts once the running fiber yields,
+// blocks or finishes.
+fn spawn(vm: &mut Vm, args: Vec<Value>) -> Result<(), RuntimeError> {
+    match &args[0] {
+        Value::Closure(closure) => vm.spawn(Rc::clone(closure)),
+        _ => Err(RuntimeError::new(
+            ErrorKind::Type,
+            "First argument to 'spawn' must be a function.",
+        )),
+    }
+}
+
+fn channel(_args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::Channel(Rc::new(ChannelObj::new())))
+}
+
+fn send(vm: &mut Vm, mut args: Vec<Value>) -> Result<(), RuntimeError> {
+    let channel = Rc::clone(channel_arg(&args[0], "send")?);
+    let value = args.pop().unwrap_or(Value::Nil);
+    vm.send(channel, value)
+}
+
+fn recv(vm: &mut Vm, args: Vec<Value>) -> Result<(), RuntimeError> {
+    let channel = channel_arg(&args[0], "recv")?;
+    vm.receive(Rc::clone(channel))
+}
+
+fn channel_arg<'a>(value: &'a Value, name: &str) -> Result<&'a Rc<ChannelObj>, RuntimeError> {
+    match value {
+        Value::Channel(channel) => Ok(channel),
+        _ => Err(RuntimeError::new(
+            ErrorKind::Type,
+            format!("First argument to '{}' must be a channel.", name),
+        )),
+    }
+}
+
+// Calls the function with no arguments once the delay in milliseconds has
+// passed, and returns the timer's id.
+fn set_timeout(vm: &mut Vm, args: Vec<Value>) -> Result<(), RuntimeError> {
+    let ms = match args[1].as_float() {
+        Some(ms) if ms >= 0.0 => ms,
+        _ => {
+            return Err(RuntimeError::new(
+                ErrorKind::Type,
+                "Second argument to 'setTimeout' must be a non-negative number.",
+            ))
+        }
+    };
+
+    // An infinite delay, or one too long to tell when it ends, is an error.
+    let id = Duration::try_from_secs_f64(ms / 1000.0)
+        .ok()
+        .and_then(|delay| vm.events().set_timeout(args[0].clone(), delay))
+        .ok_or_else(|| {
+            RuntimeError::new(
+                ErrorKind::Value,
+                "Delay passed to 'setTimeout' is too long.",
+            )
+        })?;
+    vm.push(Value::Int(id as i64));
+    Ok(())
+}
+
+// Cancels a timer and returns whether it hadn't fired yet.
+fn clear_timeout(vm: &mut Vm, args: Vec<Value>) -> Result<(), RuntimeError> {
+    let cleared = match args[0].as_int() {
+        Some(id) => vm.events().clear_timeout(id as usize),
+        None => false,
+    };
+    vm.push(Value::Bool(cleared));
+    Ok(())
+}
+
+// Reads the file in the background and calls back with an error message or
+// nil, and the text.
+fn read_file(vm: &mut Vm, args: Vec<Value>) -> Result<(), RuntimeError> {
+    let path = args[0].as_string().ok_or_else(|| {
+        RuntimeError::new(
+            ErrorKind::Type,
+            "First argument to 'readFile' must be a string.",
+        )
+    })?;
+    vm.events().read_file(path, args[1].clone());
+    vm.push(Value::Nil);
+    Ok(())
+}
+
+// Listens on the port of 127.0.0.1, or on a free one for port 0, and calls
+// back with the socket of each connection.
+fn listen(vm: &mut Vm, args: Vec<Value>) -> Result<(), RuntimeError> {
+    let port = port_arg(&args[0], "listen")?;
+    let server = vm
+        .events()
+        .listen(port, args[1].clone())
+        .map_err(|e| RuntimeError::new(ErrorKind::Io, e.to_string()))?;
+    vm.push(Value::Handle(server));
+    Ok(())
+}
+
+// Connects to the port of 127.0.0.1 in the background and calls back with an
+// error message or nil, and the socket.
+fn connect(vm: &mut Vm, args: Vec<Value>) -> Result<(), RuntimeError> {
+    let port = port_arg(&args[0], "connect")?;
+    vm.events().connect(port, args[1].clone());
+    vm.push(Value::Nil);
+    Ok(())
+}
+
+// The port a server is listening on.
+fn port(vm: &mut Vm, args: Vec<Value>) -> Result<(), RuntimeError> {
+    let server = handle_arg(&args[0], HandleKind::Server, "port")?;
+    let port = vm
+        .events()
+        .local_port(server)
+        .map_err(|e| RuntimeError::new(ErrorKind::Io, e))?;
+    vm.push(Value::Int(port as i64));
+    Ok(())
+}
+
+// Queues the text to be sent on the socket.
+fn write(vm: &mut Vm, args: Vec<Value>) -> Result<(), RuntimeError> {
+    let socket = handle_arg(&args[0], HandleKind::Socket, "write")?;
+    let text = args[1].stringify();
+    vm.events()
+        .write(socket, &text)
+        .map_err(|e| RuntimeError::new(ErrorKind::Io, e))?;
+    vm.push(Value::Nil);
+    Ok(())
+}
+
+// Calls back with the next text to arrive on the socket, or nil once the other
+// end has closed it.
+fn read(vm: &mut Vm, args: Vec<Value>) -> Result<(), RuntimeError> {
+    let socket = handle_arg(&args[0], HandleKind::Socket, "read")?;
+    vm.events()
+        .read(socket, args[1].clone())
+        .map_err(|e| RuntimeError::new(ErrorKind::Io, e))?;
+    vm.push(Value::Nil);
+    Ok(())
+}
+
+// Stops a server, or closes a socket once what was written to it is sent.
+fn close(vm: &mut Vm, args: Vec<Value>) -> Result<(), RuntimeError> {
+    match &args[0] {
+        Value::Handle(handle) => vm.events().close(handle),
+        _ => {
+            return Err(RuntimeError::new(
+                ErrorKind::Type,
+                "First argument to 'close' must be a server or a socket.",
+            ))
+        }
+    }
+    vm.push(Value::Nil);
+    Ok(())
+}
+
+fn port_arg(value: &Value, name: &str) -> Result<u16, RuntimeError> {
+    value
+        .as_int()
+        .and_then(|n| u16::try_from(n).ok())
+        .ok_or_else(|| {
+            RuntimeError::new(
+                ErrorKind::Value,
+                format!("First argument to '{}' must be a port number.", name),
+            )
+        })
+}
+
+fn handle_arg<'a>(
+    value: &'a Value,
+    kind: HandleKind,
+    name: &str,
+) -> Result<&'a HandleObj, RuntimeError> {
+    match value {
+        Value::Handle(handle) if handle.kind == kind => Ok(handle),
+        _ => {
+            let expected = match kind {
+                HandleKind::Server => "server",
+                HandleKind::Socket => "socket",
+            };
+            Err(RuntimeError::new(
+                ErrorKind::Type,
+                format!("First argument to '{}' must be a {}.", name, expected),
+            ))
+        }
+    }
+}
+
+fn map_arg<'a>(value: &'a Value, name: &str) -> Result<&'a Rc<RefCell<MapObj>>, RuntimeError> {
+    match value {
+        Value::Map(map) => Ok(map),
+        _ => Err(RuntimeError::new(
+            ErrorKind::Type,
+            format!("First argument to '{}' must be a map.", name),
+        )),
+    }
+}
@@ -23,7 +23,7 @@ impl Obj {
 
     pub fn peek_next(&self) -> Option<Rc<Obj>> {
         match &self.next {
-            Some(obj) => Some(Rc::clone(&obj)),
+            Some(obj) => Some(Rc::clone(obj)),
             None => None,
         }
     }
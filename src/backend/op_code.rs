@@ -10,6 +10,15 @@ pub enum OpCode {
     Subtract,
     Multiply,
     Divide,
+    Modulo,
+    IntDivide,
+    Power,
+    BitAnd,
+    BitOr,
+    BitXor,
+    BitNot,
+    ShiftLeft,
+    ShiftRight,
     True,
     False,
     Nil,
@@ -25,9 +34,39 @@ pub enum OpCode {
     GetLocal(usize),
     SetLocal(usize),
     JumpIfFalse(usize),
+    JumpIfNotNil(usize),
     Jump(usize),
     Loop(usize),
     Case(usize),
+    CaseRange(usize),
+    CaseRangeInclusive(usize),
+    Import(usize),
+    ImportName(usize),
+    GetProperty(usize),
+    Try(usize),
+    PopHandler,
+    Throw,
+    Yield,
+    ForIn(usize, usize),
+    ForRange(usize, usize),
+    ForRangeInclusive(usize, usize),
+    Stringify,
+    Format(usize),
+    Closure(usize),
+    GetUpvalue(usize),
+    SetUpvalue(usize),
+    CloseUpvalue,
+    Call(usize),
+    CallNamed(usize, usize),
+    JumpIfGiven(usize, usize),
+    List(usize),
+    Map(usize),
+    GetIndex,
+    SetIndex,
+    Slice,
+    Dup2,
+    // Moves the top value beneath the two values under it.
+    Rotate,
 }
 
 impl fmt::Display for OpCode {
@@ -59,6 +98,14 @@ impl fmt::Display for OpCode {
                     width = 11
                 )
             }
+            Self::JumpIfNotNil(index) => {
+                write!(
+                    f,
+                    "JUMP_IF_NOT_NIL {number:>width$}",
+                    number = index,
+                    width = 9
+                )
+            }
             Self::Jump(index) => {
                 write!(f, "JUMP {number:>width$}", number = index, width = 20)
             }
@@ -68,12 +115,99 @@ impl fmt::Display for OpCode {
             Self::Case(index) => {
                 write!(f, "CASE {number:>width$}", number = index, width = 20)
             }
+            Self::CaseRange(index) => {
+                write!(f, "CASE_RANGE {number:>width$}", number = index, width = 14)
+            }
+            Self::CaseRangeInclusive(index) => {
+                write!(
+                    f,
+                    "CASE_RANGE_INCLUSIVE {number:>width$}",
+                    number = index,
+                    width = 4
+                )
+            }
+            Self::Import(index) => {
+                write!(f, "IMPORT {number:>width$}", number = index, width = 18)
+            }
+            Self::ImportName(index) => {
+                write!(
+                    f,
+                    "IMPORT_NAME {number:>width$}",
+                    number = index,
+                    width = 13
+                )
+            }
+            Self::GetProperty(index) => {
+                write!(
+                    f,
+                    "GET_PROPERTY {number:>width$}",
+                    number = index,
+                    width = 12
+                )
+            }
+            Self::Try(offset) => {
+                write!(f, "TRY {number:>width$}", number = offset, width = 21)
+            }
+            Self::PopHandler => write!(f, "POP_HANDLER"),
+            Self::Throw => write!(f, "THROW"),
+            Self::Yield => write!(f, "YIELD"),
+            Self::ForIn(slot, offset) => write!(f, "FOR_IN {:>18} {}", slot, offset),
+            Self::ForRange(slot, offset) => write!(f, "FOR_RANGE {:>15} {}", slot, offset),
+            Self::ForRangeInclusive(slot, offset) => {
+                write!(f, "FOR_RANGE_INCLUSIVE {:>5} {}", slot, offset)
+            }
+            Self::Format(index) => {
+                write!(f, "FORMAT {number:>width$}", number = index, width = 18)
+            }
+            Self::Closure(index) => {
+                write!(f, "CLOSURE {number:>width$}", number = index, width = 17)
+            }
+            Self::GetUpvalue(index) => {
+                write!(
+                    f,
+                    "GET_UPVALUE {number:>width$}",
+                    number = index,
+                    width = 13
+                )
+            }
+            Self::SetUpvalue(index) => {
+                write!(
+                    f,
+                    "SET_UPVALUE {number:>width$}",
+                    number = index,
+                    width = 13
+                )
+            }
+            Self::Call(count) => {
+                write!(f, "CALL {number:>width$}", number = count, width = 20)
+            }
+            Self::CallNamed(count, index) => {
+                write!(f, "CALL_NAMED {:>14} {}", count, index)
+            }
+            Self::JumpIfGiven(param, offset) => {
+                write!(f, "JUMP_IF_GIVEN {:>11} {}", param, offset)
+            }
+            Self::List(count) => {
+                write!(f, "LIST {number:>width$}", number = count, width = 20)
+            }
+            Self::Map(count) => {
+                write!(f, "MAP {number:>width$}", number = count, width = 21)
+            }
             Self::Return => write!(f, "RETURN"),
             Self::Negate => write!(f, "NEGATE"),
             Self::Add => write!(f, "ADD"),
             Self::Subtract => write!(f, "SUBTRACT"),
             Self::Multiply => write!(f, "MULTIPLY"),
             Self::Divide => write!(f, "DIVIDE"),
+            Self::Modulo => write!(f, "MODULO"),
+            Self::IntDivide => write!(f, "INT_DIVIDE"),
+            Self::Power => write!(f, "POWER"),
+            Self::BitAnd => write!(f, "BIT_AND"),
+            Self::BitOr => write!(f, "BIT_OR"),
+            Self::BitXor => write!(f, "BIT_XOR"),
+            Self::BitNot => write!(f, "BIT_NOT"),
+            Self::ShiftLeft => write!(f, "SHIFT_LEFT"),
+            Self::ShiftRight => write!(f, "SHIFT_RIGHT"),
             Self::True => write!(f, "TRUE"),
             Self::False => write!(f, "FALSE"),
             Self::Nil => write!(f, "NIL"),
@@ -83,6 +217,13 @@ impl fmt::Display for OpCode {
             Self::Less => write!(f, "LESS"),
             Self::Print => write!(f, "PRINT"),
             Self::Pop => write!(f, "POP"),
+            Self::Stringify => write!(f, "STRINGIFY"),
+            Self::CloseUpvalue => write!(f, "CLOSE_UPVALUE"),
+            Self::GetIndex => write!(f, "GET_INDEX"),
+            Self::SetIndex => write!(f, "SET_INDEX"),
+            Self::Slice => write!(f, "SLICE"),
+            Self::Dup2 => write!(f, "DUP_2"),
+            Self::Rotate => write!(f, "ROTATE"),
         }
     }
 }
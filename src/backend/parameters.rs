@@ -0,0 +1,89 @@
+// The parameters of a function: the names of the positional ones, how many of
+// those must be passed because they have no default value, and the name of the
+// rest parameter that collects any extra arguments into a list.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Parameters {
+    pub names: Vec<String>,
+    pub required: usize,
+    pub rest: Option<String>,
+}
+
+impl Parameters {
+    pub fn arity(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn has_defaults(&self) -> bool {
+        self.required < self.arity()
+    }
+
+    pub fn accepts(&self, count: usize) -> bool {
+        count >= self.required && (count <= self.arity() || self.rest.is_some())
+    }
+
+    pub fn arity_error(&self, count: usize) -> String {
+        if self.rest.is_some() {
+            format!(
+                "Expected at least {} arguments but got {}.",
+                self.required, count
+            )
+        } else if self.has_defaults() {
+            format!(
+                "Expected {} to {} arguments but got {}.",
+                self.required,
+                self.arity(),
+                count
+            )
+        } else {
+            format!("Expected {} arguments but got {}.", self.arity(), count)
+        }
+    }
+
+    // Finds the parameter each named argument is for, given the number of
+    // positional arguments passed before them.
+    pub fn resolve(&self, positional: usize, names: &[String]) -> Result<Vec<usize>, String> {
+        let indices = names
+            .iter()
+            .map(|name| {
+                self.names
+                    .iter()
+                    .position(|param| param == name)
+                    .ok_or_else(|| format!("No parameter named '{}'.", name))
+            })
+            .collect::<Result<Vec<usize>, String>>()?;
+
+        self.check(positional, &indices)?;
+        Ok(indices)
+    }
+
+    // Checks that `positional` arguments followed by named arguments for the
+    // parameters at `indices` give each parameter at most one value, and every
+    // required parameter exactly one.
+    pub fn check(&self, positional: usize, indices: &[usize]) -> Result<(), String> {
+        let count = positional + indices.len();
+        if (positional > self.arity() && self.rest.is_none())
+            || indices.iter().any(|&index| index >= self.arity())
+        {
+            return Err(self.arity_error(count));
+        }
+
+        let mut given: Vec<bool> = (0..self.arity()).map(|i| i < positional).collect();
+        for &index in indices.iter() {
+            if given[index] {
+                return Err(format!(
+                    "Argument '{}' was given more than once.",
+                    self.names[index]
+                ));
+            }
+            given[index] = true;
+        }
+
+        match given[..self.required].iter().position(|given| !given) {
+            Some(index) => Err(format!(
+                "Missing argument for parameter '{}'.",
+                self.names[index]
+            )),
+            None => Ok(()),
+        }
+    }
+}
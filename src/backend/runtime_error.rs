@@ -0,0 +1,94 @@
+use std::fmt;
+
+use super::{map_obj::MapObj, value::Value};
+
+// Why an instruction failed: an error the VM raised, or a value the script
+// threw. Either one unwinds to the nearest `catch`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeError {
+    Raised(ErrorKind, String),
+    Thrown(Value),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    Type,
+    Name,
+    Index,
+    Key,
+    Argument,
+    Value,
+    Overflow,
+    StackOverflow,
+    Import,
+    Deadlock,
+    Io,
+    // A bug in blox rather than in the script.
+    Internal,
+}
+
+impl RuntimeError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        RuntimeError::Raised(kind, message.into())
+    }
+
+    // The value a `catch` receives. A raised error becomes a map holding its
+    // message, its kind and the line it was raised on.
+    pub fn into_value(self, line: usize) -> Value {
+        match self {
+            RuntimeError::Raised(kind, message) => {
+                let mut error = MapObj::new();
+                let fields = [
+                    ("message", Value::Str(message)),
+                    ("kind", Value::Str(kind.to_string())),
+                    ("line", Value::Int(line as i64)),
+                ];
+                for (key, value) in fields {
+                    let _ = error.insert(Value::Str(key.to_string()), value);
+                }
+                Value::map(error)
+            }
+            RuntimeError::Thrown(value) => value,
+        }
+    }
+
+    // The message reported when nothing catches the error. A thrown map with a
+    // "message" string, like a caught error thrown again, reports that.
+    pub fn message(&self) -> String {
+        match self {
+            RuntimeError::Raised(_, message) => message.clone(),
+            RuntimeError::Thrown(Value::Map(map)) => {
+                match map.borrow().get(&Value::Str(String::from("message"))) {
+                    Ok(Some(message)) if message.as_string().is_some() => message.stringify(),
+                    _ => Value::Map(map.clone()).to_string(),
+                }
+            }
+            RuntimeError::Thrown(value) => value.stringify(),
+        }
+    }
+}
+
+impl From<&str> for RuntimeError {
+    fn from(message: &str) -> Self {
+        RuntimeError::new(ErrorKind::Internal, message)
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Type => write!(f, "TypeError"),
+            Self::Name => write!(f, "NameError"),
+            Self::Index => write!(f, "IndexError"),
+            Self::Key => write!(f, "KeyError"),
+            Self::Argument => write!(f, "ArgumentError"),
+            Self::Value => write!(f, "ValueError"),
+            Self::Overflow => write!(f, "OverflowError"),
+            Self::StackOverflow => write!(f, "StackOverflowError"),
+            Self::Import => write!(f, "ImportError"),
+            Self::Deadlock => write!(f, "DeadlockError"),
+            Self::Io => write!(f, "IoError"),
+            Self::Internal => write!(f, "InternalError"),
+        }
+    }
+}
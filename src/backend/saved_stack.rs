@@ -0,0 +1,24 @@
+use std::{cell::RefCell, rc::Rc};
+
+use super::{call_frame::CallFrame, upvalue_obj::UpvalueObj, value::Value};
+
+// The stack a generator or fiber owns while it isn't running, with the frames
+// running on it and the upvalues pointing into it. Slots and handlers count
+// from the bottom of the saved values.
+pub struct SavedStack {
+    // Shared with the upvalues of the saved variables, which closures can
+    // still read and write in the meantime.
+    pub values: Rc<RefCell<Vec<Value>>>,
+    pub frames: Vec<CallFrame>,
+    pub upvalues: Vec<Rc<RefCell<UpvalueObj>>>,
+}
+
+impl SavedStack {
+    pub fn new(values: Vec<Value>, frames: Vec<CallFrame>) -> Self {
+        Self {
+            values: Rc::new(RefCell::new(values)),
+            frames,
+            upvalues: vec![],
+        }
+    }
+}
@@ -1,27 +1,27 @@
 use std::fmt;
 use std::rc::Rc;
 
+// A string literal that is still a slice of the source code, which is shared
+// rather than copied. `start` and `len` are byte offsets.
 #[derive(Debug, Clone, PartialEq)]
 pub struct SourceStr {
     start: usize,
     len: usize,
-    source: Rc<Vec<char>>,
+    source: Rc<str>,
 }
 
 impl SourceStr {
-    pub fn new(start: usize, len: usize, source: Rc<Vec<char>>) -> Self {
+    pub fn new(start: usize, len: usize, source: Rc<str>) -> Self {
         SourceStr { start, len, source }
     }
+
+    pub fn as_str(&self) -> &str {
+        &self.source[self.start..self.start + self.len]
+    }
 }
 
 impl fmt::Display for SourceStr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "{}",
-            self.source[self.start..self.start + self.len]
-                .iter()
-                .collect::<String>()
-        )
+        write!(f, "{}", self.as_str())
     }
 }
@@ -0,0 +1,131 @@
+use std::ops::Range;
+
+use super::{
+    runtime_error::{ErrorKind, RuntimeError},
+    value::Value,
+};
+
+// Lists and strings can be indexed by position, counting back from the end
+// when the index is negative. Strings are indexed by character, and maps by
+// key.
+pub fn get(target: &Value, index: &Value) -> Result<Value, RuntimeError> {
+    match target {
+        Value::Map(map) => match map.borrow().get(index)? {
+            Some(value) => Ok(value.clone()),
+            None => Err(RuntimeError::new(
+                ErrorKind::Key,
+                format!("Undefined key {}.", index),
+            )),
+        },
+        Value::List(items) => {
+            let items = items.borrow();
+            let position = position(index, items.len(), "list")?;
+            Ok(items[position].clone())
+        }
+        _ => match target.as_string() {
+            Some(string) => {
+                let chars: Vec<char> = string.chars().collect();
+                let position = position(index, chars.len(), "string")?;
+                Ok(Value::Str(chars[position].to_string()))
+            }
+            None => Err(RuntimeError::new(
+                ErrorKind::Type,
+                "Can only index lists, maps and strings.",
+            )),
+        },
+    }
+}
+
+pub fn set(target: &Value, index: &Value, value: Value) -> Result<(), RuntimeError> {
+    match target {
+        Value::List(items) => {
+            let mut items = items.borrow_mut();
+            let position = position(index, items.len(), "list")?;
+            items[position] = value;
+            Ok(())
+        }
+        Value::Map(map) => map.borrow_mut().insert(index.clone(), value),
+        _ => Err(RuntimeError::new(
+            ErrorKind::Type,
+            "Can only assign to elements of a list or map.",
+        )),
+    }
+}
+
+// Copies the items from `start` up to `end` into a new list or string. Either
+// bound may be nil, and bounds past either end are clamped to it.
+pub fn slice(target: &Value, start: &Value, end: &Value) -> Result<Value, RuntimeError> {
+    match target {
+        Value::List(items) => {
+            let items = items.borrow();
+            let range = range(start, end, items.len())?;
+            Ok(Value::list(items[range].to_vec()))
+        }
+        _ => match target.as_string() {
+            Some(string) => {
+                let chars: Vec<char> = string.chars().collect();
+                let range = range(start, end, chars.len())?;
+                Ok(Value::Str(chars[range].iter().collect()))
+            }
+            None => Err(RuntimeError::new(
+                ErrorKind::Type,
+                "Can only slice lists and strings.",
+            )),
+        },
+    }
+}
+
+// The position an element can be inserted at, from the start of the list up to
+// and including its end.
+pub fn insert_position(index: &Value, len: usize) -> Result<usize, RuntimeError> {
+    let index = integer(index)?;
+    let position = if index < 0 { index + len as i64 } else { index };
+    if position < 0 || position > len as i64 {
+        return Err(out_of_bounds(index, len, "list"));
+    }
+
+    Ok(position as usize)
+}
+
+fn position(index: &Value, len: usize, kind: &str) -> Result<usize, RuntimeError> {
+    let index = integer(index)?;
+    let position = if index < 0 { index + len as i64 } else { index };
+    if position < 0 || position >= len as i64 {
+        return Err(out_of_bounds(index, len, kind));
+    }
+
+    Ok(position as usize)
+}
+
+fn range(start: &Value, end: &Value, len: usize) -> Result<Range<usize>, RuntimeError> {
+    let bound = |value: &Value, default: usize| -> Result<usize, RuntimeError> {
+        let index = match value {
+            Value::Nil => return Ok(default),
+            value => value.as_int(),
+        }
+        .ok_or_else(|| RuntimeError::new(ErrorKind::Type, "Slice bounds must be integers."))?;
+
+        let position = if index < 0 { index + len as i64 } else { index };
+        Ok(position.clamp(0, len as i64) as usize)
+    };
+
+    let start = bound(start, 0)?;
+    let end = bound(end, len)?;
+    Ok(start..end.max(start))
+}
+
+fn integer(index: &Value) -> Result<i64, RuntimeError> {
+    index
+        .as_int()
+        .ok_or_else(|| RuntimeError::new(ErrorKind::Type, "Index must be an integer."))
+}
+
+fn out_of_bounds(index: i64, len: usize, kind: &str) -> RuntimeError {
+    RuntimeError::new(
+        ErrorKind::Index,
+        format!(
+            "Index {} is out of bounds for {} of length {}.",
+            index, kind, len
+        ),
+    )
+}
@@ -0,0 +1,14 @@
+use std::{cell::RefCell, rc::Rc};
+
+use super::value::Value;
+
+// A variable captured by a closure. It points at the variable's stack slot
+// while the variable is in scope, and holds the value itself once the scope
+// has ended. The slot of a variable in a suspended generator is in the stack
+// segment the generator keeps.
+#[derive(Debug, Clone, PartialEq)]
+pub enum UpvalueObj {
+    Open(usize),
+    Suspended(Rc<RefCell<Vec<Value>>>, usize),
+    Closed(Value),
+}
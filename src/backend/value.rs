@@ -1,36 +1,211 @@
-use std::fmt;
+use std::{cell::RefCell, cmp::Ordering, fmt, rc::Rc};
 
-use super::{source_str::SourceStr, function_obj::FunctionObj};
+use super::{
+    channel_obj::ChannelObj, closure_obj::ClosureObj, fiber_obj::FiberObj,
+    function_obj::FunctionObj, generator_obj::GeneratorObj, handle_obj::HandleObj, map_obj::MapObj,
+    module_obj::ModuleObj, native_obj::NativeObj, source_str::SourceStr,
+};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Value {
+    Int(i64),
     Number(f64),
     Bool(bool),
     Str(String),
     SourceStr(SourceStr),
     VarIdent(String),
     ValIdent(String),
-    Function(FunctionObj),
+    Function(Rc<FunctionObj>),
+    Closure(Rc<ClosureObj>),
+    Native(Rc<NativeObj>),
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<MapObj>>),
+    Module(Rc<ModuleObj>),
+    Generator(Rc<GeneratorObj>),
+    Fiber(Rc<FiberObj>),
+    Channel(Rc<ChannelObj>),
+    Handle(Rc<HandleObj>),
     Nil,
 }
 
 impl Value {
+    pub fn list(items: Vec<Value>) -> Value {
+        Value::List(Rc::new(RefCell::new(items)))
+    }
+
+    pub fn map(map: MapObj) -> Value {
+        Value::Map(Rc::new(RefCell::new(map)))
+    }
+
     pub fn is_falsey(&self) -> bool {
         matches!(self, Value::Bool(false) | Value::Nil)
     }
+
+    pub fn is_number(&self) -> bool {
+        matches!(self, Value::Int(_) | Value::Number(_))
+    }
+
+    // Any number as a float, which loses precision for integers beyond 2^53.
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Value::Int(n) => Some(*n as f64),
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    // An integer, or a float without a fractional part that fits in one.
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Int(n) => Some(*n),
+            Value::Number(n) => as_integer(*n),
+            _ => None,
+        }
+    }
+
+    // Values of different types are never equal, except that an integer equals
+    // the float with the same value. Strings compare by content no
+    // matter where they are stored, handles by the server or socket they stand
+    // for, and other objects, like functions, lists and maps, by identity.
+    pub fn equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Int(a), Value::Number(b)) | (Value::Number(b), Value::Int(a)) => {
+                compare_int_float(*a, *b) == Some(Ordering::Equal)
+            }
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Nil, Value::Nil) => true,
+            (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+            (Value::Closure(a), Value::Closure(b)) => Rc::ptr_eq(a, b),
+            (Value::Native(a), Value::Native(b)) => Rc::ptr_eq(a, b),
+            (Value::List(a), Value::List(b)) => Rc::ptr_eq(a, b),
+            (Value::Map(a), Value::Map(b)) => Rc::ptr_eq(a, b),
+            (Value::Module(a), Value::Module(b)) => Rc::ptr_eq(a, b),
+            (Value::Generator(a), Value::Generator(b)) => Rc::ptr_eq(a, b),
+            (Value::Fiber(a), Value::Fiber(b)) => Rc::ptr_eq(a, b),
+            (Value::Channel(a), Value::Channel(b)) => Rc::ptr_eq(a, b),
+            (Value::Handle(a), Value::Handle(b)) => a == b,
+            (Value::VarIdent(a), Value::VarIdent(b)) | (Value::ValIdent(a), Value::ValIdent(b)) => {
+                a == b
+            }
+            _ => match (self.as_string(), other.as_string()) {
+                (Some(a), Some(b)) => a == b,
+                _ => false,
+            },
+        }
+    }
+
+    // Numbers are ordered numerically and strings lexicographically. Any other
+    // pair of values, or a NaN, can't be ordered.
+    pub fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+            (Value::Number(a), Value::Number(b)) => a.partial_cmp(b),
+            (Value::Int(a), Value::Number(b)) => compare_int_float(*a, *b),
+            (Value::Number(a), Value::Int(b)) => compare_int_float(*b, *a).map(Ordering::reverse),
+            _ => match (self.as_string(), other.as_string()) {
+                (Some(a), Some(b)) => Some(a.cmp(&b)),
+                _ => None,
+            },
+        }
+    }
+
+    // The text a value contributes to an interpolated string. Unlike `Display`,
+    // strings are written without their quotes.
+    pub fn stringify(&self) -> String {
+        self.as_string().unwrap_or_else(|| self.to_string())
+    }
+
+    pub fn as_string(&self) -> Option<String> {
+        match self {
+            Value::Str(s) => Some(s.clone()),
+            Value::SourceStr(s) => Some(s.to_string()),
+            _ => None,
+        }
+    }
 }
 
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            Self::Int(n) => write!(f, "{}", n),
             Self::Number(n) => write!(f, "{}", n),
             Self::Bool(b) => write!(f, "{}", b),
             Self::Str(s) => write!(f, "\"{}\"", s),
             Self::SourceStr(s) => write!(f, "\"{}\"", s),
             Self::VarIdent(s) => write!(f, "<var {}>", s),
             Self::ValIdent(s) => write!(f, "<val {}>", s),
-            Self::Function(o) => write!(f, "{}", o.name),
+            Self::Function(o) => write!(f, "{}", o),
+            Self::Closure(o) => write!(f, "{}", o),
+            Self::Native(o) => write!(f, "{}", o),
+            Self::List(items) => display_once(f, Rc::as_ptr(items) as usize, "[...]", |f| {
+                write!(f, "[")?;
+                for (index, item) in items.borrow().iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }),
+            Self::Map(map) => write!(f, "{}", map.borrow()),
+            Self::Module(module) => write!(f, "{}", module),
+            Self::Generator(generator) => write!(f, "{}", generator),
+            Self::Fiber(fiber) => write!(f, "{}", fiber),
+            Self::Channel(channel) => write!(f, "{}", channel),
+            Self::Handle(handle) => write!(f, "{}", handle),
             Self::Nil => write!(f, "nil"),
         }
     }
 }
+
+thread_local! {
+    // Where the lists and maps being displayed live, outermost first.
+    static DISPLAYING: RefCell<Vec<usize>> = const { RefCell::new(vec![]) };
+}
+
+// Displays the list or map at `address`, or writes `repeat` in its place when
+// it contains itself and is already being displayed further out.
+pub fn display_once<F>(
+    f: &mut fmt::Formatter,
+    address: usize,
+    repeat: &str,
+    display: F,
+) -> fmt::Result
+where
+    F: FnOnce(&mut fmt::Formatter) -> fmt::Result,
+{
+    if DISPLAYING.with(|displaying| displaying.borrow().contains(&address)) {
+        return write!(f, "{}", repeat);
+    }
+
+    DISPLAYING.with(|displaying| displaying.borrow_mut().push(address));
+    let result = display(f);
+    DISPLAYING.with(|displaying| displaying.borrow_mut().pop());
+    result
+}
+
+pub fn as_integer(n: f64) -> Option<i64> {
+    // `i64::MAX as f64` rounds up to 2^63, which is itself out of range.
+    if n.fract() == 0.0 && n >= i64::MIN as f64 && n < i64::MAX as f64 {
+        Some(n as i64)
+    } else {
+        None
+    }
+}
+
+// Compares exactly, where converting the integer to a float could round it.
+// Floats with a fractional part are below 2^52, where integers convert exactly,
+// and whole floats that aren't integers are beyond every integer.
+fn compare_int_float(a: i64, b: f64) -> Option<Ordering> {
+    match as_integer(b) {
+        Some(b) => Some(a.cmp(&b)),
+        None if b.fract() == 0.0 => Some(if b > 0.0 {
+            Ordering::Less
+        } else {
+            Ordering::Greater
+        }),
+        None => (a as f64).partial_cmp(&b),
+    }
+}
@@ -26,15 +26,8 @@ impl ValueArray {
     pub fn find_identifier(&self, query: &str) -> Option<(usize, Value)> {
         for (index, constant) in self.values.iter().enumerate() {
             match (*constant).borrow() {
-                Value::ValIdent(name) => {
-                    if name == query {
-                        return Some((index, constant.clone()));
-                    }
-                }
-                Value::VarIdent(name) => {
-                    if name == query {
-                        return Some((index, constant.clone()));
-                    }
+                Value::ValIdent(name) | Value::VarIdent(name) if name == query => {
+                    return Some((index, constant.clone()));
                 }
                 _ => (),
             }
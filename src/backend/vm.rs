@@ -1,16 +1,39 @@
 use std::{
-    collections::HashMap,
+    cell::RefCell,
+    cmp::Ordering,
+    collections::{HashMap, VecDeque},
     fs,
     io::{self, BufRead, Write},
+    mem,
+    path::{Path, PathBuf},
     rc::Rc,
-    cell::RefCell,
     str,
 };
 
 use crate::{error::codes::ErrCode, frontend::compiler::Compiler, frontend::scanner::Scanner};
 
 use super::{
-    call_frame::CallFrame, function_obj::FunctionType, obj::Obj, op_code::OpCode, value::Value,
+    arithmetic::{self, Arithmetic, Overflow},
+    call_frame::{CallFrame, Handler},
+    channel_obj::ChannelObj,
+    closure_obj::ClosureObj,
+    event_loop::EventLoop,
+    fiber_obj::{FiberObj, FiberState},
+    format_spec::FormatSpec,
+    function_obj::FunctionType,
+    generator_obj::{GeneratorObj, GeneratorState},
+    iteration,
+    map_obj::MapObj,
+    module_obj::ModuleObj,
+    native_obj::NativeBody,
+    natives,
+    obj::Obj,
+    op_code::OpCode,
+    runtime_error::{ErrorKind, RuntimeError},
+    saved_stack::SavedStack,
+    subscript,
+    upvalue_obj::UpvalueObj,
+    value::Value,
 };
 
 const FRAMES_MAX: usize = 64;
@@ -21,9 +44,33 @@ pub struct Vm {
     stack: Vec<Value>,
     objects: Option<Rc<Obj>>,
     frames: Vec<CallFrame>,
-    globals: HashMap<String, Value>,
+    // The module of the script being run, whose globals the REPL keeps between
+    // lines.
+    main: Rc<ModuleObj>,
+    // The natives, which every module can see unless it defines a global with
+    // the same name.
+    builtins: HashMap<String, Value>,
+    // Every module imported so far, by canonical path.
+    modules: HashMap<PathBuf, Rc<ModuleObj>>,
+    // Directories searched for a module that isn't found next to the file
+    // importing it.
+    search_path: Vec<PathBuf>,
+    // Timers and I/O the script is waiting on, which call back once the
+    // script and its fibers are done.
+    events: EventLoop,
+    // The fiber running now, and the fibers waiting for their turn.
+    fiber: Rc<FiberObj>,
+    ready: VecDeque<Rc<FiberObj>>,
+    // The fibers waiting to receive, and the channel each is waiting on.
+    blocked: Vec<(Rc<FiberObj>, Rc<ChannelObj>)>,
+    // Upvalues still pointing at stack slots, which are closed when their
+    // variables go out of scope.
+    open_upvalues: Vec<Rc<RefCell<UpvalueObj>>>,
     debug_print_code: bool,
     debug_trace: bool,
+    lox_compat: bool,
+    print_docs: bool,
+    overflow: Overflow,
 }
 
 impl Vm {
@@ -33,12 +80,43 @@ impl Vm {
             stack: Vec::with_capacity(STACK_MAX),
             objects: None,
             frames: Vec::with_capacity(FRAMES_MAX),
-            globals: HashMap::new(),
+            main: Rc::new(ModuleObj::new(String::from("script"), PathBuf::new())),
+            builtins: HashMap::new(),
+            modules: HashMap::new(),
+            search_path: vec![],
+            events: EventLoop::new(),
+            fiber: Rc::new(FiberObj::main()),
+            ready: VecDeque::new(),
+            blocked: vec![],
+            open_upvalues: vec![],
             debug_print_code: false,
             debug_trace: false,
+            lox_compat: false,
+            print_docs: false,
+            overflow: Overflow::default(),
         }
     }
 
+    // Rejects blox extensions and follows the semantics of reference Lox.
+    pub fn set_lox_compat(&mut self, lox_compat: bool) {
+        self.lox_compat = lox_compat;
+    }
+
+    // Prints the doc comments of the script's declarations, including those in
+    // function bodies, before it runs.
+    pub fn set_print_docs(&mut self, print_docs: bool) {
+        self.print_docs = print_docs;
+    }
+
+    // Chooses what integer arithmetic does when a result doesn't fit in 64 bits.
+    pub fn set_overflow(&mut self, overflow: Overflow) {
+        self.overflow = overflow;
+    }
+
+    pub fn add_search_path(&mut self, dir: &str) {
+        self.search_path.push(PathBuf::from(dir));
+    }
+
     pub fn repl(&mut self) -> Result<(), ErrCode> {
         println!("=== Welcome to blox v1.0");
         println!("=== Enter 'q' or 'Q' to quit");
@@ -76,45 +154,189 @@ impl Vm {
     ) -> Result<(), ErrCode> {
         self.debug_print_code = debug_print_code;
         self.debug_trace = debug_trace;
+        let main = Rc::new(ModuleObj::new(path.to_string(), PathBuf::from(path)));
+        if let Ok(canonical) = fs::canonicalize(path) {
+            self.modules.insert(canonical, Rc::clone(&main));
+        }
+        self.main = main;
         fs::read_to_string(path)
             .map_err(|e| ErrCode::Io(e.to_string()))
             .and_then(|source| self.interpret(source))
     }
 
     pub fn interpret(&mut self, source: String) -> Result<(), ErrCode> {
-        let compiler =
-            Compiler::new(Rc::new(RefCell::new(Scanner::new(source))), None, String::new(), FunctionType::Script, self.debug_print_code).compile()?;
-        let frame = CallFrame::new(compiler.function, 0, 0);
+        let compiler = self.compile(source, None)?;
+        if self.print_docs {
+            println!("== docs ==");
+            for doc in compiler.function.all_docs() {
+                println!("{}", doc);
+            }
+        }
+
+        self.define_natives();
+        self.fiber = Rc::new(FiberObj::main());
+        let function = Rc::new(compiler.function);
+        let closure = Rc::new(ClosureObj::new(function, vec![], Rc::clone(&self.main)));
+        self.stack.push(Value::Closure(Rc::clone(&closure)));
+        let mut frame = CallFrame::new(closure, 0, self.stack.len(), vec![]);
+        frame.module = true;
         self.frames.push(frame);
         self.frame_count = self.frames.len();
         self.objects = compiler.objects;
-        self.run()
+        self.run()?;
+        self.run_event_loop()
+    }
+
+    // Calls back as the script's timers and I/O complete, each callback in a
+    // fiber of its own, until nothing is left pending.
+    fn run_event_loop(&mut self) -> Result<(), ErrCode> {
+        while let Some((callback, args)) = self.events.next_event() {
+            self.fiber = Rc::new(FiberObj::main());
+            let count = args.len();
+            self.stack.push(callback.clone());
+            self.stack.extend(args);
+            if let Err(e) = self.call_value(callback, count, None) {
+                self.throw(e)?;
+            }
+            self.run()?;
+            self.stack.clear();
+        }
+
+        Ok(())
+    }
+
+    pub fn events(&mut self) -> &mut EventLoop {
+        &mut self.events
+    }
+
+    pub fn push(&mut self, value: Value) {
+        self.stack.push(value);
+    }
+
+    // Compiles the script, or the module named `module_name`.
+    fn compile(&self, source: String, module_name: Option<&str>) -> Result<Compiler, ErrCode> {
+        let scanner = Rc::new(RefCell::new(Scanner::new(source, self.lox_compat)));
+        let function_type = FunctionType::Script;
+        let mut compiler = Compiler::new(
+            scanner,
+            None,
+            String::new(),
+            function_type,
+            self.debug_print_code,
+        );
+        compiler.set_lox_compat(self.lox_compat);
+        if let Some(name) = module_name {
+            compiler.set_module_name(name.to_string());
+        }
+        compiler.compile()
+    }
+
+    fn define_natives(&mut self) {
+        for (native, in_lox) in natives::natives() {
+            if in_lox || !self.lox_compat {
+                self.builtins
+                    .entry(native.name.to_string())
+                    .or_insert_with(|| Value::Native(Rc::new(native)));
+            }
+        }
     }
 
     fn run(&mut self) -> Result<(), ErrCode> {
-        while self.frame().ip <= self.frame().function.chunk.count() {
+        while self.frame_count > 0 || self.next_fiber()? {
+            let ip = self.frame().ip;
+            let op = self.frame().closure.function.chunk.code[ip];
             if self.debug_trace {
                 self.stack_trace();
-                let ip = self.frame().ip;
-                let op = self.frame().function.chunk.code[ip];
-                self.frame().function.chunk.disassamble_instruction(ip, &op)
+                self.frame()
+                    .closure
+                    .function
+                    .chunk
+                    .disassamble_instruction(ip, &op)
             }
 
-            let op_result = self.match_op();
-            if let Err(e) = op_result {
-                return Err(ErrCode::Runtime(self.runtime_error(&e)));
+            self.frame().ip += 1;
+            if let Err(e) = self.match_op(op) {
+                self.throw(e)?;
             }
+        }
 
-            self.frame().ip += 1;
+        Ok(())
+    }
+
+    // Unwinds to the innermost `try` still running and hands its handler the
+    // error, or ends the script with the error when nothing catches it.
+    fn throw(&mut self, error: RuntimeError) -> Result<(), ErrCode> {
+        let catching = match self
+            .frames
+            .iter()
+            .rposition(|frame| !frame.handlers.is_empty())
+        {
+            Some(index) => index,
+            None => {
+                let message = self.runtime_error(&error.message());
+                self.reset_stack();
+                return Err(ErrCode::Runtime(message));
+            }
+        };
+
+        let line = {
+            let frame = self.frame();
+            frame.closure.function.chunk.get_line(frame.ip - 1)
+        };
+        while self.frame_count > catching + 1 {
+            let slots_start = self.frame().slots_start;
+            self.close_upvalues(slots_start);
+            let frame = self
+                .frames
+                .pop()
+                .expect("the frame is above the catching one");
+            if frame.module {
+                // The module didn't finish loading, so a later import tries again.
+                let module = &frame.closure.module;
+                self.modules.retain(|_, loaded| !Rc::ptr_eq(loaded, module));
+            }
+            if let Some(generator) = frame.generator {
+                generator.state.set(GeneratorState::Done);
+            }
+            self.frame_count -= 1;
         }
 
+        let handler = self
+            .frame()
+            .handlers
+            .pop()
+            .expect("the frame has a handler");
+        self.close_upvalues(handler.stack_len);
+        self.stack.truncate(handler.stack_len);
+        self.stack.push(error.into_value(line));
+        self.frame().ip = handler.ip;
         Ok(())
     }
 
-    fn match_op(&mut self) -> Result<(), String> {
-        let ip = self.frame().ip;
-        match self.frame().function.chunk.code[ip] {
-            OpCode::Return => Ok(()),
+    fn match_op(&mut self, op: OpCode) -> Result<(), RuntimeError> {
+        match op {
+            OpCode::Return => {
+                let mut result = self.stack.pop().ok_or("Not enough values on the stack")?;
+                let slots_start = self.frame().slots_start;
+                self.close_upvalues(slots_start);
+                let frame = self.frames.pop().ok_or("No frame to return from")?;
+                if frame.module {
+                    frame.closure.module.loaded.set(true);
+                    result = Value::Module(Rc::clone(&frame.closure.module));
+                }
+                if let Some(generator) = frame.generator {
+                    generator.state.set(GeneratorState::Done);
+                }
+                self.frame_count -= 1;
+
+                // Discard the callee and its slots, leaving the result in its place.
+                self.stack.truncate(slots_start - 1);
+                if self.frame_count > 0 {
+                    self.stack.push(result);
+                }
+
+                Ok(())
+            }
             OpCode::Constant(index) => {
                 let value = self.get_constant(index);
                 self.stack.push(value);
@@ -122,41 +344,74 @@ impl Vm {
             }
             OpCode::Negate => {
                 let top = self.stack_top();
-                if let Value::Number(n) = self.stack[top] {
-                    self.stack[top] = Value::Number(-n);
-                    return Ok(());
+                match self.stack[top] {
+                    Value::Int(n) => {
+                        self.stack[top] = arithmetic::negate(n, self.overflow)?;
+                        Ok(())
+                    }
+                    Value::Number(n) => {
+                        self.stack[top] = Value::Number(-n);
+                        Ok(())
+                    }
+                    _ => Err(RuntimeError::new(
+                        ErrorKind::Type,
+                        "Operand must be a number.",
+                    )),
                 }
-
-                Err(self.runtime_error("Operand must be a number"))
             }
             OpCode::Add => {
                 let (left, right) = self.get_left_right()?;
 
                 match (&right, &left) {
                     (Value::SourceStr(r), Value::SourceStr(l)) => {
-                        self.concat_strings(&l.to_string(), &r.to_string());
+                        self.concat_strings(l.as_str(), r.as_str());
                     }
                     (Value::SourceStr(r), Value::Str(l)) => {
-                        self.concat_strings(l, &r.to_string());
+                        self.concat_strings(l, r.as_str());
                     }
                     (Value::Str(r), Value::SourceStr(l)) => {
-                        self.concat_strings(&l.to_string(), r);
+                        self.concat_strings(l.as_str(), r);
                     }
                     (Value::Str(r), Value::Str(l)) => {
                         self.concat_strings(l, r);
                     }
-                    (Value::Number(r), Value::Number(l)) => {
-                        let value = Value::Number(l + r);
-                        self.stack.push(value);
-                    }
-                    _ => return Err(String::from("Operands must be two numbers or two strings")),
+                    _ => match arithmetic::apply(Arithmetic::Add, &left, &right, self.overflow) {
+                        Some(value) => self.stack.push(value?),
+                        None => {
+                            return Err(RuntimeError::new(
+                                ErrorKind::Type,
+                                "Operands must be two numbers or two strings.",
+                            ))
+                        }
+                    },
                 };
 
                 Ok(())
             }
-            OpCode::Subtract => self.binary_op(|right, left| Value::Number(left - right)),
-            OpCode::Multiply => self.binary_op(|right, left| Value::Number(left * right)),
-            OpCode::Divide => self.binary_op(|right, left| Value::Number(left / right)),
+            OpCode::Subtract => self.binary_op(Arithmetic::Subtract),
+            OpCode::Multiply => self.binary_op(Arithmetic::Multiply),
+            OpCode::Divide => self.binary_op(Arithmetic::Divide),
+            OpCode::Modulo => self.binary_op(Arithmetic::Modulo),
+            OpCode::IntDivide => self.binary_op(Arithmetic::IntDivide),
+            OpCode::Power => self.binary_op(Arithmetic::Power),
+            OpCode::BitAnd => self.integer_op(|right, left| Ok(left & right)),
+            OpCode::BitOr => self.integer_op(|right, left| Ok(left | right)),
+            OpCode::BitXor => self.integer_op(|right, left| Ok(left ^ right)),
+            OpCode::ShiftLeft => self.integer_op(|right, left| Ok(left << shift_amount(right)?)),
+            OpCode::ShiftRight => self.integer_op(|right, left| Ok(left >> shift_amount(right)?)),
+            OpCode::BitNot => {
+                let top = self.stack_top();
+                match self.stack[top].as_int() {
+                    Some(n) => {
+                        self.stack[top] = Value::Int(!n);
+                        Ok(())
+                    }
+                    None => Err(RuntimeError::new(
+                        ErrorKind::Type,
+                        "Operand must be an integer.",
+                    )),
+                }
+            }
             OpCode::True => {
                 self.stack.push(Value::Bool(true));
                 Ok(())
@@ -176,72 +431,80 @@ impl Vm {
             }
             OpCode::Equal => {
                 let (left, right) = self.get_left_right()?;
-
-                let value = match (right, left) {
-                    (Value::Number(r), Value::Number(l)) => Value::Bool(l == r),
-                    (Value::SourceStr(r), Value::SourceStr(l)) => {
-                        Value::Bool(l.to_string() == r.to_string())
-                    }
-                    (Value::SourceStr(r), Value::Str(l)) => Value::Bool(l == r.to_string()),
-                    (Value::Str(r), Value::SourceStr(l)) => Value::Bool(l.to_string() == r),
-                    (Value::Str(b), Value::Str(a)) => Value::Bool(a == b),
-                    (Value::Bool(b), Value::Bool(a)) => Value::Bool(a == b),
-                    _ => {
-                        return Err(String::from(
-                            "Operands must be two numbers, two strings, or two booleans",
-                        ))
-                    }
-                };
-
-                self.stack.push(value);
+                self.stack.push(Value::Bool(left.equals(&right)));
+                Ok(())
+            }
+            OpCode::Greater => self.compare_op(|ordering| ordering == Ordering::Greater),
+            OpCode::Less => self.compare_op(|ordering| ordering == Ordering::Less),
+            OpCode::Stringify => {
+                let value = self.stack.pop().ok_or("Not enough values on the stack")?;
+                self.stack.push(Value::Str(value.stringify()));
+                Ok(())
+            }
+            OpCode::Format(index) => {
+                let spec = self.get_constant(index).stringify();
+                let spec = FormatSpec::parse(&spec).ok_or_else(|| {
+                    let message = format!("Invalid format specifier '{}'.", spec);
+                    RuntimeError::new(ErrorKind::Value, message)
+                })?;
+                let value = self.stack.pop().ok_or("Not enough values on the stack")?;
+                self.stack.push(Value::Str(spec.apply(&value)));
                 Ok(())
             }
-            OpCode::Greater => self.binary_op(|right, left| Value::Bool(left > right)),
-            OpCode::Less => self.binary_op(|right, left| Value::Bool(left < right)),
             OpCode::Print => self.print_value(),
             OpCode::Pop => match self.stack.pop() {
                 Some(_) => Ok(()),
-                None => Err(String::from("Not enough values on the stack")),
+                None => Err(RuntimeError::from("Not enough values on the stack")),
             },
             OpCode::DefGlobal(index) => {
                 let name = self.get_constant(index);
                 match name {
                     Value::VarIdent(n) | Value::ValIdent(n) => {
-                        let top = self.stack_top();
-                        self.globals.insert(n.clone(), self.stack[top].clone());
-                        self.stack.pop();
+                        let value = self.stack.pop().ok_or("Not enough values on the stack")?;
+                        self.module().globals.borrow_mut().insert(n, value);
                         Ok(())
                     }
-                    _ => Err(String::from("Not a valid identifier")),
+                    _ => Err(RuntimeError::from("Not a valid identifier")),
                 }
             }
             OpCode::GetGlobal(index) => {
                 let name = self.get_constant(index);
                 match &name {
-                    Value::VarIdent(n) | Value::ValIdent(n) => match self.globals.get(n) {
+                    Value::VarIdent(n) | Value::ValIdent(n) => match self.get_global(n) {
                         Some(value) => {
-                            self.stack.push(value.clone());
+                            self.stack.push(value);
                             Ok(())
                         }
-                        None => Err(format!("Undefined variable {}", n)),
+                        None => Err(RuntimeError::new(
+                            ErrorKind::Name,
+                            format!("Undefined variable '{}'.", n),
+                        )),
                     },
-                    _ => Err(String::from("Not a valid identifier")),
+                    _ => Err(RuntimeError::from("Not a valid identifier")),
                 }
             }
             OpCode::SetGlobal(index) => {
                 let name_ref = self.get_constant(index);
                 let name = match &name_ref {
                     Value::VarIdent(name) | Value::ValIdent(name) => name,
-                    _ => return Err(String::from("Not a valid identifier")),
+                    _ => return Err(RuntimeError::from("Not a valid identifier")),
                 };
 
+                // Assigning to a native defines a global that hides it.
                 let top = self.stack_top();
-                if self.globals.contains_key(name) {
-                    self.globals.insert(name.clone(), self.stack[top].clone());
+                let module = self.module();
+                if module.globals.borrow().contains_key(name) || self.builtins.contains_key(name) {
+                    module
+                        .globals
+                        .borrow_mut()
+                        .insert(name.clone(), self.stack[top].clone());
                     return Ok(());
                 }
 
-                Err(format!("Undefined variable {}", name_ref))
+                Err(RuntimeError::new(
+                    ErrorKind::Name,
+                    format!("Undefined variable '{}'.", name),
+                ))
             }
             OpCode::GetLocal(slot) => {
                 let offset = self.frame().slots_start + slot;
@@ -263,6 +526,14 @@ impl Vm {
 
                 Ok(())
             }
+            OpCode::JumpIfNotNil(offset) => {
+                let top = self.stack_top();
+                if !matches!(self.stack[top], Value::Nil) {
+                    self.frame().ip += offset;
+                }
+
+                Ok(())
+            }
             OpCode::Jump(offset) => {
                 self.frame().ip += offset;
                 Ok(())
@@ -272,78 +543,843 @@ impl Vm {
                 Ok(())
             }
             OpCode::Case(offset) => {
+                let value = self.stack.pop();
                 let top = self.stack_top();
-                let below = top - 1;
-                if self.stack.len() < 2 {
-                    return Err(String::from("Not enough values on the stack"));
+                match value {
+                    Some(value) if self.stack[top].equals(&value) => self.frame().ip += offset,
+                    Some(_) => (),
+                    None => return Err(RuntimeError::from("Not enough values on the stack")),
                 }
 
-                match (&self.stack[top], &self.stack[below]) {
-                    (Value::Number(r), Value::Number(l)) => {
-                        if r != l {
-                            self.frame().ip += offset;
-                            self.stack.pop();
-                        }
-                    }
-                    (Value::SourceStr(r), Value::SourceStr(l)) => {
-                        if l.to_string() != r.to_string() {
-                            self.frame().ip += offset;
-                            self.stack.pop();
-                        }
-                    }
-                    (Value::SourceStr(r), Value::Str(l)) => {
-                        if l != &r.to_string() {
-                            self.frame().ip += offset;
-                            self.stack.pop();
-                        }
-                    }
-                    (Value::Str(r), Value::SourceStr(l)) => {
-                        if &l.to_string() != r {
-                            self.frame().ip += offset;
-                            self.stack.pop();
-                        }
+                Ok(())
+            }
+            OpCode::CaseRange(offset) => self.case_range(offset, false),
+            OpCode::CaseRangeInclusive(offset) => self.case_range(offset, true),
+            OpCode::ForIn(slot, offset) => {
+                let base = self.frame().slots_start + slot;
+                if let Value::Generator(generator) = &self.stack[base] {
+                    let generator = Rc::clone(generator);
+                    return self.for_in_generator(base, offset, generator);
+                }
+
+                let cursor = match self.stack[base + 1] {
+                    Value::Int(n) => n as usize,
+                    _ => 0,
+                };
+
+                match iteration::next(&self.stack[base], cursor)? {
+                    Some((item, cursor)) => {
+                        self.stack[base + 1] = Value::Int(cursor as i64);
+                        self.stack.push(item);
                     }
-                    (Value::Str(r), Value::Str(l)) => {
-                        if l != r {
-                            self.frame().ip += offset;
-                            self.stack.pop();
-                        }
+                    None => self.frame().ip += offset,
+                }
+
+                Ok(())
+            }
+            OpCode::Import(index) => {
+                let spec = self.get_constant(index).stringify();
+                self.import(&spec)
+            }
+            OpCode::ImportName(index) => {
+                let name = self.get_constant(index).stringify();
+                let top = self.stack_top();
+                let value = self.property(&self.stack[top], &name)?;
+                self.stack.push(value);
+                Ok(())
+            }
+            OpCode::GetProperty(index) => {
+                let name = self.get_constant(index).stringify();
+                let target = self.stack.pop().ok_or("Not enough values on the stack")?;
+                let value = self.property(&target, &name)?;
+                self.stack.push(value);
+                Ok(())
+            }
+            OpCode::Try(offset) => {
+                let handler = Handler::new(self.frame().ip + offset, self.stack.len());
+                self.frame().handlers.push(handler);
+                Ok(())
+            }
+            OpCode::PopHandler => {
+                self.frame().handlers.pop();
+                Ok(())
+            }
+            OpCode::Throw => {
+                let value = self.stack.pop().ok_or("Not enough values on the stack")?;
+                Err(RuntimeError::Thrown(value))
+            }
+            OpCode::Yield => {
+                let value = self.stack.pop().ok_or("Not enough values on the stack")?;
+                match self
+                    .frames
+                    .iter()
+                    .rposition(|frame| frame.generator.is_some())
+                {
+                    Some(bottom) => self.suspend(bottom, value),
+                    // The function a fiber was spawned with yields to the
+                    // other fibers instead.
+                    None => {
+                        self.yield_fiber();
+                        Ok(())
                     }
-                    (Value::Bool(r), Value::Bool(l)) => {
-                        if l != r {
-                            self.frame().ip += offset;
-                            self.stack.pop();
-                        }
+                }
+            }
+            OpCode::ForRange(slot, offset) => self.for_range(slot, offset, false),
+            OpCode::ForRangeInclusive(slot, offset) => self.for_range(slot, offset, true),
+            OpCode::Closure(index) => {
+                let function = match self.get_constant(index) {
+                    Value::Function(function) => function,
+                    _ => return Err(RuntimeError::from("Not a function")),
+                };
+
+                let slots_start = self.frame().slots_start;
+                let mut upvalues = Vec::with_capacity(function.captures.len());
+                for capture in function.captures.iter() {
+                    let upvalue = if capture.is_local {
+                        self.capture_upvalue(slots_start + capture.index)
+                    } else {
+                        Rc::clone(&self.frame().closure.upvalues[capture.index])
+                    };
+                    upvalues.push(upvalue);
+                }
+
+                let module = Rc::clone(&self.frame().closure.module);
+                let closure = ClosureObj::new(function, upvalues, module);
+                self.stack.push(Value::Closure(Rc::new(closure)));
+                Ok(())
+            }
+            OpCode::GetUpvalue(index) => {
+                let upvalue = Rc::clone(&self.frame().closure.upvalues[index]);
+                let value = match &*upvalue.borrow() {
+                    UpvalueObj::Open(slot) => self.stack[*slot].clone(),
+                    UpvalueObj::Suspended(stack, slot) => stack.borrow()[*slot].clone(),
+                    UpvalueObj::Closed(value) => value.clone(),
+                };
+                self.stack.push(value);
+                Ok(())
+            }
+            OpCode::SetUpvalue(index) => {
+                let upvalue = Rc::clone(&self.frame().closure.upvalues[index]);
+                let value = self.stack[self.stack_top()].clone();
+                match &mut *upvalue.borrow_mut() {
+                    UpvalueObj::Open(slot) => self.stack[*slot] = value,
+                    UpvalueObj::Suspended(stack, slot) => stack.borrow_mut()[*slot] = value,
+                    UpvalueObj::Closed(closed) => *closed = value,
+                }
+                Ok(())
+            }
+            OpCode::CloseUpvalue => {
+                self.close_upvalues(self.stack_top());
+                self.stack.pop();
+                Ok(())
+            }
+            OpCode::Call(count) => {
+                let callee = self.stack[self.stack.len() - 1 - count].clone();
+                self.call_value(callee, count, None)
+            }
+            OpCode::CallNamed(count, index) => {
+                let callee = self.stack[self.stack.len() - 1 - count].clone();
+                let names = self.get_constant(index);
+                self.call_value(callee, count, Some(names))
+            }
+            OpCode::List(count) => {
+                let items = self.stack.split_off(self.stack.len() - count);
+                self.stack.push(Value::list(items));
+                Ok(())
+            }
+            OpCode::Map(count) => {
+                let entries = self.stack.split_off(self.stack.len() - 2 * count);
+                let mut map = MapObj::new();
+                for entry in entries.chunks(2) {
+                    map.insert(entry[0].clone(), entry[1].clone())?;
+                }
+                self.stack.push(Value::map(map));
+                Ok(())
+            }
+            OpCode::GetIndex => {
+                let (target, index) = self.get_left_right()?;
+                self.stack.push(subscript::get(&target, &index)?);
+                Ok(())
+            }
+            OpCode::SetIndex => {
+                let value = self.stack.pop().ok_or("Not enough values on the stack")?;
+                let (target, index) = self.get_left_right()?;
+                subscript::set(&target, &index, value.clone())?;
+                self.stack.push(value);
+                Ok(())
+            }
+            OpCode::Slice => {
+                let end = self.stack.pop().ok_or("Not enough values on the stack")?;
+                let (target, start) = self.get_left_right()?;
+                self.stack.push(subscript::slice(&target, &start, &end)?);
+                Ok(())
+            }
+            OpCode::Dup2 => {
+                let top = self.stack_top();
+                self.stack.push(self.stack[top - 1].clone());
+                self.stack.push(self.stack[top].clone());
+                Ok(())
+            }
+            OpCode::Rotate => {
+                let value = self.stack.pop().ok_or("Not enough values on the stack")?;
+                let below = self.stack_top() - 1;
+                self.stack.insert(below, value);
+                Ok(())
+            }
+            OpCode::JumpIfGiven(param, offset) => {
+                if self.frame().given.get(param).copied().unwrap_or(true) {
+                    self.frame().ip += offset;
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    // `names` holds the names of the trailing named arguments, or the indices of
+    // their parameters when the compiler could work them out.
+    fn call_value(
+        &mut self,
+        callee: Value,
+        count: usize,
+        names: Option<Value>,
+    ) -> Result<(), RuntimeError> {
+        match (callee, names) {
+            (Value::Closure(closure), None) => self.call(closure, count),
+            (Value::Closure(closure), Some(names)) => self.call_named(closure, count, names),
+            (Value::Native(_), Some(_)) => Err(RuntimeError::new(
+                ErrorKind::Argument,
+                "Can't pass named arguments to a native function.",
+            )),
+            (Value::Native(native), None) => {
+                if count != native.arity {
+                    return Err(RuntimeError::new(
+                        ErrorKind::Argument,
+                        format!("Expected {} arguments but got {}.", native.arity, count),
+                    ));
+                }
+
+                let args = self.stack.split_off(self.stack.len() - count);
+                self.stack.pop();
+                match native.function {
+                    NativeBody::Value(function) => {
+                        let result = function(&args)?;
+                        self.stack.push(result);
+                        Ok(())
                     }
-                    _ => return Err(String::from("Mismatched types in case statement")),
+                    NativeBody::Vm(function) => function(self, args),
                 }
+            }
+            _ => Err(RuntimeError::new(
+                ErrorKind::Type,
+                "Can only call functions and classes.",
+            )),
+        }
+    }
 
+    fn call(&mut self, closure: Rc<ClosureObj>, count: usize) -> Result<(), RuntimeError> {
+        let params = &closure.function.params;
+        if !params.accepts(count) {
+            return Err(RuntimeError::new(
+                ErrorKind::Argument,
+                params.arity_error(count),
+            ));
+        }
+
+        let slots_start = self.stack.len() - count;
+        let given = (0..params.arity()).map(|i| i < count).collect();
+        for _ in count..params.arity() {
+            self.stack.push(Value::Nil);
+        }
+
+        self.enter(closure, slots_start, given)
+    }
+
+    fn call_named(
+        &mut self,
+        closure: Rc<ClosureObj>,
+        count: usize,
+        names: Value,
+    ) -> Result<(), RuntimeError> {
+        let params = &closure.function.params;
+        let names = match names {
+            Value::List(names) => names,
+            _ => return Err(RuntimeError::from("Not a list of argument names")),
+        };
+
+        let positional = count - names.borrow().len();
+        let names: Vec<String> = names.borrow().iter().map(Value::stringify).collect();
+        let indices = params
+            .resolve(positional, &names)
+            .map_err(|e| RuntimeError::new(ErrorKind::Argument, e))?;
+
+        let slots_start = self.stack.len() - count;
+        let named = self.stack.split_off(slots_start + positional);
+        let mut given: Vec<bool> = (0..params.arity()).map(|i| i < positional).collect();
+        for _ in positional..params.arity() {
+            self.stack.push(Value::Nil);
+        }
+
+        for (index, value) in indices.into_iter().zip(named) {
+            self.stack[slots_start + index] = value;
+            given[index] = true;
+        }
+
+        self.enter(closure, slots_start, given)
+    }
+
+    // Pushes the frame of a call whose positional parameters have their slots,
+    // after collecting any arguments past them into the rest parameter.
+    fn enter(
+        &mut self,
+        closure: Rc<ClosureObj>,
+        slots_start: usize,
+        given: Vec<bool>,
+    ) -> Result<(), RuntimeError> {
+        if self.frame_count == FRAMES_MAX {
+            return Err(RuntimeError::new(
+                ErrorKind::StackOverflow,
+                "Stack overflow.",
+            ));
+        }
+
+        let params = &closure.function.params;
+        if params.rest.is_some() {
+            let extra = self.stack.split_off(slots_start + params.arity());
+            self.stack.push(Value::list(extra));
+        }
+
+        let given = if params.has_defaults() { given } else { vec![] };
+        if closure.function.generator {
+            // The body doesn't run until the generator is resumed, so the call
+            // and its arguments move to the generator's own segment.
+            let stack = self.stack.split_off(slots_start - 1);
+            let frame = CallFrame::new(Rc::clone(&closure), 0, 1, given);
+            let generator = GeneratorObj::new(closure, stack, frame);
+            self.stack.push(Value::Generator(Rc::new(generator)));
+            return Ok(());
+        }
+
+        self.frames
+            .push(CallFrame::new(closure, 0, slots_start, given));
+        self.frame_count += 1;
+        Ok(())
+    }
+
+    // Moves a suspended generator's segment and frames back on top of the stack,
+    // so it runs until it yields or returns a value in place of the call that
+    // resumed it.
+    pub fn resume(&mut self, generator: Rc<GeneratorObj>) -> Result<(), RuntimeError> {
+        match generator.state.get() {
+            GeneratorState::Suspended => (),
+            GeneratorState::Running => {
+                return Err(RuntimeError::new(
+                    ErrorKind::Value,
+                    "Generator is already running.",
+                ))
+            }
+            GeneratorState::Done => {
+                return Err(RuntimeError::new(
+                    ErrorKind::Value,
+                    "Can't resume a finished generator.",
+                ))
+            }
+        }
+
+        if self.frame_count + generator.saved.borrow().frames.len() > FRAMES_MAX {
+            return Err(RuntimeError::new(
+                ErrorKind::StackOverflow,
+                "Stack overflow.",
+            ));
+        }
+
+        let bottom = self.restore(&generator.saved);
+        self.frames[bottom].generator = Some(Rc::clone(&generator));
+        generator.state.set(GeneratorState::Running);
+        Ok(())
+    }
+
+    // Takes the running generator's frames and segment off the stack, leaving
+    // the value it yields for the code that resumed it.
+    fn suspend(&mut self, bottom: usize, value: Value) -> Result<(), RuntimeError> {
+        let generator = self.frames[bottom]
+            .generator
+            .take()
+            .ok_or("Not in a generator")?;
+        let base = self.frames[bottom].slots_start - 1;
+        self.save(&generator.saved, bottom, base);
+        generator.state.set(GeneratorState::Suspended);
+        self.stack.push(value);
+        Ok(())
+    }
+
+    // Moves the frames from `bottom` up and the stack from `base` up into
+    // `saved`, rebasing their slots and the upvalues pointing into them.
+    fn save(&mut self, saved: &RefCell<SavedStack>, bottom: usize, base: usize) {
+        let mut saved = saved.borrow_mut();
+        let mut frames = self.frames.split_off(bottom);
+        for frame in frames.iter_mut() {
+            frame.slots_start -= base;
+            for handler in frame.handlers.iter_mut() {
+                handler.stack_len -= base;
+            }
+        }
+        self.frame_count = self.frames.len();
+        saved.frames = frames;
+
+        let values = Rc::clone(&saved.values);
+        let mut upvalues = vec![];
+        self.open_upvalues.retain(|upvalue| {
+            let slot = match *upvalue.borrow() {
+                UpvalueObj::Open(slot) if slot >= base => slot,
+                _ => return true,
+            };
+            *upvalue.borrow_mut() = UpvalueObj::Suspended(Rc::clone(&values), slot - base);
+            upvalues.push(Rc::clone(upvalue));
+            false
+        });
+        saved.upvalues = upvalues;
+
+        *values.borrow_mut() = self.stack.split_off(base);
+    }
+
+    // Moves the saved stack and frames back on top of the running ones, and
+    // returns the index of the first restored frame.
+    fn restore(&mut self, saved: &RefCell<SavedStack>) -> usize {
+        let mut saved = saved.borrow_mut();
+        let base = self.stack.len();
+        self.stack.append(&mut saved.values.borrow_mut());
+        for upvalue in mem::take(&mut saved.upvalues) {
+            let slot = match *upvalue.borrow() {
+                UpvalueObj::Suspended(_, slot) => slot,
+                _ => continue,
+            };
+            *upvalue.borrow_mut() = UpvalueObj::Open(base + slot);
+            self.open_upvalues.push(upvalue);
+        }
+
+        let bottom = self.frames.len();
+        for mut frame in mem::take(&mut saved.frames) {
+            frame.slots_start += base;
+            for handler in frame.handlers.iter_mut() {
+                handler.stack_len += base;
+            }
+            self.frames.push(frame);
+        }
+        self.frame_count = self.frames.len();
+        bottom
+    }
+
+    // Makes a fiber that calls the function, which takes no arguments, once
+    // the fibers ahead of it have had their turn.
+    pub fn spawn(&mut self, closure: Rc<ClosureObj>) -> Result<(), RuntimeError> {
+        let params = &closure.function.params;
+        if !params.accepts(0) {
+            return Err(RuntimeError::new(
+                ErrorKind::Argument,
+                params.arity_error(0),
+            ));
+        }
+
+        let mut stack = vec![Value::Closure(Rc::clone(&closure))];
+        stack.extend((0..params.arity()).map(|_| Value::Nil));
+        if params.rest.is_some() {
+            stack.push(Value::list(vec![]));
+        }
+        let given = if params.has_defaults() {
+            vec![false; params.arity()]
+        } else {
+            vec![]
+        };
+
+        let name = closure.function.name.clone();
+        let frame = CallFrame::new(closure, 0, 1, given);
+        let fiber = Rc::new(FiberObj::new(name, SavedStack::new(stack, vec![frame])));
+        self.ready.push_back(Rc::clone(&fiber));
+        self.stack.push(Value::Fiber(fiber));
+        Ok(())
+    }
+
+    // Sends without waiting. A fiber blocked on the channel receives the value
+    // directly and is ready to run again.
+    pub fn send(&mut self, channel: Rc<ChannelObj>, value: Value) -> Result<(), RuntimeError> {
+        let receiver = channel.receivers.borrow_mut().pop_front();
+        match receiver {
+            Some(fiber) => {
+                fiber.push(value);
+                fiber.state.set(FiberState::Ready);
+                self.blocked
+                    .retain(|(blocked, _)| !Rc::ptr_eq(blocked, &fiber));
+                self.ready.push_back(fiber);
+            }
+            None => channel.buffer.borrow_mut().push_back(value),
+        }
+
+        self.stack.push(Value::Nil);
+        Ok(())
+    }
+
+    // Receives the oldest value sent on the channel, or blocks the running
+    // fiber until one is sent.
+    pub fn receive(&mut self, channel: Rc<ChannelObj>) -> Result<(), RuntimeError> {
+        let value = channel.buffer.borrow_mut().pop_front();
+        if let Some(value) = value {
+            self.stack.push(value);
+            return Ok(());
+        }
+
+        if self.ready.is_empty() && !self.events.is_pending() {
+            return Err(deadlock());
+        }
+
+        let fiber = Rc::clone(&self.fiber);
+        fiber.state.set(FiberState::Blocked);
+        channel.receivers.borrow_mut().push_back(Rc::clone(&fiber));
+        self.blocked.push((fiber, channel));
+        self.switch_fiber();
+        Ok(())
+    }
+
+    // Lets the other ready fibers run before the running one continues.
+    fn yield_fiber(&mut self) {
+        if self.ready.is_empty() {
+            return;
+        }
+
+        self.fiber.state.set(FiberState::Ready);
+        self.ready.push_back(Rc::clone(&self.fiber));
+        self.switch_fiber();
+    }
+
+    // Saves the running fiber's stacks and runs the next ready fiber. With none
+    // ready the VM is left idle until the event loop wakes a fiber.
+    fn switch_fiber(&mut self) {
+        let current = Rc::clone(&self.fiber);
+        self.save(&current.saved, 0, 0);
+        if let Some(next) = self.ready.pop_front() {
+            self.fiber = Rc::clone(&next);
+            self.restore(&next.saved);
+            next.state.set(FiberState::Running);
+        }
+    }
+
+    // Called once no fiber is running, to run the next ready one. Fibers left
+    // blocked with nothing ready and nothing pending in the event loop to wake
+    // them are deadlocked, and the one that has waited longest gets the error.
+    fn next_fiber(&mut self) -> Result<bool, ErrCode> {
+        if self.fiber.state.get() == FiberState::Running {
+            self.fiber.state.set(FiberState::Done);
+        }
+
+        let mut deadlocked = false;
+        let next = match self.ready.pop_front() {
+            Some(next) => next,
+            None if self.blocked.is_empty() || self.events.is_pending() => return Ok(false),
+            None => {
+                let (next, channel) = self.blocked.remove(0);
+                channel
+                    .receivers
+                    .borrow_mut()
+                    .retain(|fiber| !Rc::ptr_eq(fiber, &next));
+                deadlocked = true;
+                next
+            }
+        };
+
+        self.fiber = Rc::clone(&next);
+        self.restore(&next.saved);
+        next.state.set(FiberState::Running);
+        if deadlocked {
+            self.throw(deadlock())?;
+        }
+        Ok(true)
+    }
+
+    // Steps a for-in loop over a generator by resuming it and running the loop
+    // instruction again once it has yielded. The cursor slot is true in
+    // between, and a value the generator returns rather than yields ends the
+    // loop.
+    fn for_in_generator(
+        &mut self,
+        base: usize,
+        offset: usize,
+        generator: Rc<GeneratorObj>,
+    ) -> Result<(), RuntimeError> {
+        if self.stack[base + 1] == Value::Bool(true) {
+            self.stack[base + 1] = Value::Int(0);
+            if generator.is_done() {
+                self.stack.pop();
+                self.frame().ip += offset;
+            }
+            return Ok(());
+        }
+
+        if generator.is_done() {
+            self.frame().ip += offset;
+            return Ok(());
+        }
+
+        self.resume(generator)?;
+        self.stack[base + 1] = Value::Bool(true);
+        let resumer = self.frame_count - 2;
+        self.frames[resumer].ip -= 1;
+        Ok(())
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<UpvalueObj>> {
+        let open = UpvalueObj::Open(slot);
+        if let Some(upvalue) = self.open_upvalues.iter().find(|u| *u.borrow() == open) {
+            return Rc::clone(upvalue);
+        }
+
+        let upvalue = Rc::new(RefCell::new(open));
+        self.open_upvalues.push(Rc::clone(&upvalue));
+        upvalue
+    }
+
+    // Moves the variables in the stack slots from `last` up into the upvalues
+    // that captured them.
+    fn close_upvalues(&mut self, last: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let slot = match *upvalue.borrow() {
+                UpvalueObj::Open(slot) => slot,
+                _ => return false,
+            };
+            if slot < last {
+                return true;
+            }
+
+            *upvalue.borrow_mut() = UpvalueObj::Closed(stack[slot].clone());
+            false
+        });
+    }
+
+    // Jumps when the switch subject below the bounds is a number in the range.
+    fn case_range(&mut self, offset: usize, inclusive: bool) -> Result<(), RuntimeError> {
+        let (low, high) = self.get_left_right()?;
+        if !low.is_number() || !high.is_number() {
+            return Err(RuntimeError::new(
+                ErrorKind::Type,
+                "Case range bounds must be numbers.",
+            ));
+        }
+
+        let top = self.stack_top();
+        let n = &self.stack[top];
+        if n.is_number() {
+            let above_low = matches!(n.compare(&low), Some(Ordering::Greater | Ordering::Equal));
+            let below_high = match n.compare(&high) {
+                Some(Ordering::Less) => true,
+                Some(Ordering::Equal) => inclusive,
+                _ => false,
+            };
+            if above_low && below_high {
+                self.frame().ip += offset;
+            }
+        }
+
+        Ok(())
+    }
+
+    // The module the running code belongs to, which holds its globals.
+    fn module(&mut self) -> Rc<ModuleObj> {
+        Rc::clone(&self.frame().closure.module)
+    }
+
+    fn get_global(&mut self, name: &str) -> Option<Value> {
+        let module = self.module();
+        let value = module.globals.borrow().get(name).cloned();
+        value.or_else(|| self.builtins.get(name).cloned())
+    }
+
+    fn property(&self, target: &Value, name: &str) -> Result<Value, RuntimeError> {
+        match target {
+            Value::Module(module) => match module.globals.borrow().get(name) {
+                Some(value) => Ok(value.clone()),
+                None => Err(RuntimeError::new(
+                    ErrorKind::Name,
+                    format!("Undefined variable '{}' in module '{}'.", name, module.name),
+                )),
+            },
+            _ => Err(RuntimeError::new(
+                ErrorKind::Type,
+                "Only modules have properties.",
+            )),
+        }
+    }
+
+    // Pushes the namespace of the module `spec` names. A module imported for
+    // the first time is compiled and its top level called, which returns the
+    // namespace once it has run.
+    fn import(&mut self, spec: &str) -> Result<(), RuntimeError> {
+        let path = self.find_module(spec)?;
+        let canonical = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+        if let Some(module) = self.modules.get(&canonical) {
+            if !module.loaded.get() {
+                return Err(self.import_cycle(module));
+            }
+
+            self.stack.push(Value::Module(Rc::clone(module)));
+            return Ok(());
+        }
+
+        let source = fs::read_to_string(&path).map_err(|e| {
+            let message = format!("Can't read module '{}': {}.", spec, e);
+            RuntimeError::new(ErrorKind::Import, message)
+        })?;
+        let compiler = self.compile(source, Some(spec)).map_err(|_| {
+            let message = format!("Can't compile module '{}'.", spec);
+            RuntimeError::new(ErrorKind::Import, message)
+        })?;
+
+        let module = Rc::new(ModuleObj::new(spec.to_string(), path));
+        self.modules.insert(canonical, Rc::clone(&module));
+        let closure = Rc::new(ClosureObj::new(Rc::new(compiler.function), vec![], module));
+        self.stack.push(Value::Closure(Rc::clone(&closure)));
+        self.enter(closure, self.stack.len(), vec![])?;
+        self.frame().module = true;
+        Ok(())
+    }
+
+    // Looks for the module next to the file importing it, then in each
+    // directory of the search path.
+    fn find_module(&mut self, spec: &str) -> Result<PathBuf, RuntimeError> {
+        let importer = self.module();
+        let dir = importer.path.parent().unwrap_or_else(|| Path::new(""));
+        let found = std::iter::once(dir)
+            .chain(self.search_path.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(spec))
+            .find(|path| path.is_file());
+
+        found.ok_or_else(|| {
+            RuntimeError::new(ErrorKind::Import, format!("Can't find module '{}'.", spec))
+        })
+    }
+
+    // The error for importing a module whose top level is still running, which
+    // names the modules in the cycle.
+    fn import_cycle(&self, module: &Rc<ModuleObj>) -> RuntimeError {
+        let mut names: Vec<&str> = self
+            .frames
+            .iter()
+            .filter(|frame| frame.module)
+            .map(|frame| &frame.closure.module)
+            .skip_while(|loading| !Rc::ptr_eq(loading, module))
+            .map(|loading| loading.name.as_str())
+            .collect();
+        names.push(&module.name);
+        RuntimeError::new(
+            ErrorKind::Import,
+            format!("Import cycle: {}.", names.join(" -> ")),
+        )
+    }
+
+    // Steps a range loop, whose current number, end and step are in the three
+    // slots from `slot`.
+    fn for_range(
+        &mut self,
+        slot: usize,
+        offset: usize,
+        inclusive: bool,
+    ) -> Result<(), RuntimeError> {
+        let base = self.frame().slots_start + slot;
+        let (current, end, step) = (
+            &self.stack[base],
+            &self.stack[base + 1],
+            &self.stack[base + 2],
+        );
+        match iteration::next_in_range(current, end, step, inclusive)? {
+            Some((number, next)) => {
+                self.stack[base] = next;
+                self.stack.push(number);
+            }
+            None => self.frame().ip += offset,
+        }
+
+        Ok(())
+    }
+
+    fn binary_op(&mut self, op: Arithmetic) -> Result<(), RuntimeError> {
+        let (left, right) = self.get_left_right()?;
+        // Reference Lox follows IEEE 754, where dividing by zero gives an
+        // infinity or NaN.
+        if self.lox_compat && op == Arithmetic::Divide {
+            if let Some((a, b)) = left.as_float().zip(right.as_float()) {
+                self.stack.push(Value::Number(a / b));
+                return Ok(());
+            }
+        }
+
+        match arithmetic::apply(op, &left, &right, self.overflow) {
+            Some(value) => {
+                self.stack.push(value?);
                 Ok(())
             }
+            None => Err(RuntimeError::new(
+                ErrorKind::Type,
+                "Operands must be numbers.",
+            )),
         }
     }
 
-    fn binary_op<F>(&mut self, mut op: F) -> Result<(), String>
+    // Bitwise operators work on integers, and on floats without a fractional
+    // part, as 64-bit two's complement integers.
+    fn integer_op<F>(&mut self, op: F) -> Result<(), RuntimeError>
     where
-        F: FnMut(f64, f64) -> Value,
+        F: Fn(i64, i64) -> Result<i64, RuntimeError>,
     {
         let (left, right) = self.get_left_right()?;
-        match (left, right) {
-            (Value::Number(b), Value::Number(a)) => {
-                self.stack.push(op(a, b));
+        match left.as_int().zip(right.as_int()) {
+            Some((a, b)) => {
+                self.stack.push(Value::Int(op(b, a)?));
                 Ok(())
             }
-            _ => Err(String::from("Operand must be a number")),
+            None => Err(RuntimeError::new(
+                ErrorKind::Type,
+                "Operands must be integers.",
+            )),
         }
     }
 
-    fn print_value(&mut self) -> Result<(), String> {
+    fn compare_op<F>(&mut self, op: F) -> Result<(), RuntimeError>
+    where
+        F: Fn(Ordering) -> bool,
+    {
+        let (left, right) = self.get_left_right()?;
+        let comparable = match (&left, &right) {
+            _ if left.is_number() && right.is_number() => true,
+            _ => !self.lox_compat && left.as_string().is_some() && right.as_string().is_some(),
+        };
+
+        if !comparable {
+            return Err(RuntimeError::new(
+                ErrorKind::Type,
+                if self.lox_compat {
+                    "Operands must be numbers."
+                } else {
+                    "Operands must be two numbers or two strings."
+                },
+            ));
+        }
+
+        self.stack
+            .push(Value::Bool(left.compare(&right).is_some_and(op)));
+        Ok(())
+    }
+
+    fn print_value(&mut self) -> Result<(), RuntimeError> {
         match self.stack.pop() {
+            // Reference Lox prints strings without quotes.
+            Some(value) if self.lox_compat => {
+                println!("{}", value.stringify());
+                Ok(())
+            }
             Some(value) => {
                 println!("{}", value);
                 Ok(())
             }
-            None => Err(String::from("Not enough values on the stack")),
+            None => Err(RuntimeError::from("Not enough values on the stack")),
         }
     }
 
@@ -352,26 +1388,26 @@ impl Vm {
         self.stack.push(string);
     }
 
-    fn is_falsey(&mut self) -> Result<bool, String> {
+    fn is_falsey(&mut self) -> Result<bool, RuntimeError> {
         let value = self.stack.pop();
         if let Some(v) = value {
             return Ok(v.is_falsey());
         }
 
-        Err(String::from("Not enough values on stack"))
+        Err(RuntimeError::from("Not enough values on stack"))
     }
 
-    fn get_left_right(&mut self) -> Result<(Value, Value), String> {
+    fn get_left_right(&mut self) -> Result<(Value, Value), RuntimeError> {
         let right_ref = self.stack.pop();
         let left_ref = self.stack.pop();
         match (right_ref, left_ref) {
-            (None, _) | (_, None) => return Err(String::from("Not enough values on the stack")),
+            (None, _) | (_, None) => Err(RuntimeError::from("Not enough values on the stack")),
             (Some(right), Some(left)) => Ok((left, right)),
         }
     }
 
     fn get_constant(&mut self, index: usize) -> Value {
-        (*self.frame().function.chunk.constants)
+        (*self.frame().closure.function.chunk.constants)
             .borrow()
             .get(index)
             .clone()
@@ -385,13 +1421,36 @@ impl Vm {
         self.stack.len() - 1
     }
 
+    // The error message followed by a trace of the calls in progress, innermost
+    // first.
     fn runtime_error(&mut self, msg: &str) -> String {
-        let frame = self.frame();
-        format!(
-            "{}\n[line {}] in script\n",
-            msg,
-            frame.function.chunk.get_line(frame.ip)
-        )
+        let mut error = format!("{}\n", msg);
+        for frame in self.frames.iter().rev() {
+            let function = &frame.closure.function;
+            let line = function.chunk.get_line(frame.ip - 1);
+            let location = if function.name.is_empty() {
+                format!("[line {}] in script", line)
+            } else {
+                format!("[line {}] in {}", line, function)
+            };
+            if Rc::ptr_eq(&frame.closure.module, &self.main) {
+                error.push_str(&format!("{}\n", location));
+            } else {
+                error.push_str(&format!("{} ({})\n", location, frame.closure.module.name));
+            }
+        }
+
+        error
+    }
+
+    fn reset_stack(&mut self) {
+        self.stack.clear();
+        self.frames.clear();
+        self.frame_count = 0;
+        self.open_upvalues.clear();
+        self.ready.clear();
+        self.blocked.clear();
+        self.events = EventLoop::new();
     }
 
     fn stack_trace(&self) {
@@ -403,3 +1462,18 @@ impl Vm {
         println!();
     }
 }
+
+fn deadlock() -> RuntimeError {
+    RuntimeError::new(ErrorKind::Deadlock, "Deadlock: every fiber is blocked.")
+}
+
+fn shift_amount(n: i64) -> Result<i64, RuntimeError> {
+    if (0..64).contains(&n) {
+        Ok(n)
+    } else {
+        Err(RuntimeError::new(
+            ErrorKind::Value,
+            "Shift amount must be between 0 and 63.",
+        ))
+    }
+}
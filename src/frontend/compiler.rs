@@ -1,36 +1,72 @@
-use std::{mem, rc::Rc, cell::RefCell};
+use std::{cell::RefCell, collections::HashMap, mem, rc::Rc};
 
 use crate::{
     backend::chunk::Chunk,
     backend::obj::Obj,
     backend::op_code::OpCode,
     backend::value::Value,
-    backend::{function_obj::FunctionObj, function_obj::FunctionType, source_str::SourceStr},
+    backend::{doc_comment::DocComment, format_spec::FormatSpec},
+    backend::{function_obj::Capture, function_obj::FunctionObj, function_obj::FunctionType},
+    backend::{parameters::Parameters, source_str::SourceStr},
     error::codes::ErrCode,
 };
 
 use super::{
-    local::Local, precedence::Precedence, scanner::Scanner, token::Token, token_type::TokenType,
+    local::Local,
+    loop_context::LoopContext,
+    precedence::Precedence,
+    scanner::Scanner,
+    token::Token,
+    token_type::TokenType,
+    try_context::{TryContext, TryExit, TryRegion, COMPLETED, EXIT, THROWN},
 };
 
+type ParseFn = Box<dyn Fn(&mut Compiler)>;
+
 pub struct Compiler {
     pub scanner: Rc<RefCell<Scanner>>,
     pub objects: Option<Rc<Obj>>,
     locals: Vec<Local>,
     local_count: usize,
+    loops: Vec<LoopContext>,
+    tries: Vec<TryContext>,
     scope_depth: usize,
     panic_mode: bool,
     had_error: bool,
     declaration_start: TokenType,
+    pending_doc: Option<String>,
     current: Token,
     previous: Token,
     pub function: FunctionObj,
     function_type: FunctionType,
+    // The compiler of the function this one is nested in, which is set aside
+    // while this one compiles.
+    enclosing: Option<Box<Compiler>>,
+    // Parameters of the global functions declared so far, which let calls with
+    // named arguments be checked at compile time.
+    functions: HashMap<String, Rc<Parameters>>,
+    // The last variable read whose value is a known function, and where.
+    known_callee: Option<(usize, Rc<Parameters>)>,
+    // Where the code of the left operand of the infix operator being compiled
+    // starts.
+    operand_start: usize,
+    // Whether each '(' scanned ahead of the parser, by where it starts, opens
+    // the parameters of an arrow function.
+    arrow_parameters: HashMap<usize, bool>,
     debug_print_code: bool,
+    lox_compat: bool,
+    // The module being compiled, which errors name, or `None` for the script.
+    module_name: Option<String>,
 }
 
 impl Compiler {
-    pub fn new(scanner: Rc<RefCell<Scanner>>, objects: Option<Rc<Obj>>, function_name: String, function_type: FunctionType, debug_print_code: bool) -> Self {
+    pub fn new(
+        scanner: Rc<RefCell<Scanner>>,
+        objects: Option<Rc<Obj>>,
+        function_name: String,
+        function_type: FunctionType,
+        debug_print_code: bool,
+    ) -> Self {
         let locals = Self::init_locals();
         let local_count = locals.len();
         Self {
@@ -38,18 +74,38 @@ impl Compiler {
             objects,
             locals,
             local_count,
+            loops: vec![],
+            tries: vec![],
             scope_depth: 0,
             panic_mode: false,
             had_error: false,
             declaration_start: TokenType::None,
+            pending_doc: None,
             current: Token::empty(),
             previous: Token::empty(),
             function: FunctionObj::new(function_name),
             function_type,
+            enclosing: None,
+            functions: HashMap::new(),
+            known_callee: None,
+            operand_start: 0,
+            arrow_parameters: HashMap::new(),
             debug_print_code,
+            lox_compat: false,
+            module_name: None,
         }
     }
 
+    // Rejects blox extensions, like `Vm::set_lox_compat`. The scanner is told
+    // separately, since it decides which words are keywords.
+    pub fn set_lox_compat(&mut self, lox_compat: bool) {
+        self.lox_compat = lox_compat;
+    }
+
+    pub fn set_module_name(&mut self, module_name: String) {
+        self.module_name = Some(module_name);
+    }
+
     fn init_locals() -> Vec<Local> {
         let mut local = Local::new(Token::empty(), TokenType::None);
         local.depth = 0;
@@ -85,7 +141,8 @@ impl Compiler {
     }
 
     fn end_compiler(mut self) -> Result<Compiler, ErrCode> {
-        self.consume(TokenType::Eof, "Expect end of expression");
+        self.consume(TokenType::Eof, "Expect end of expression.");
+        self.emit_return();
         if self.had_error {
             return Err(ErrCode::Compile);
         }
@@ -125,10 +182,21 @@ impl Compiler {
 
     fn declaration(&mut self) {
         self.declaration_start = self.current.typ;
-        if self.match_and_advance(TokenType::Fun) {
-            self.fun_declaration();
+        self.pending_doc = self.current.doc.take();
+        if self.pending_doc.is_some() && !self.declaration_start.is_documentable() {
+            self.pending_doc = None;
+            self.warning(
+                "Doc comment is not attached to a declaration.",
+                self.current.start,
+                self.current.length,
+                self.current.line,
+            );
         }
-        else if self.match_and_advance(TokenType::Var) {
+
+        if self.check(TokenType::Fun) && !self.lambda_ahead() {
+            self.advance();
+            self.fun_declaration();
+        } else if self.match_and_advance(TokenType::Var) {
             self.var_declaration();
         } else if self.match_and_advance(TokenType::Val) {
             self.val_declaration();
@@ -142,51 +210,214 @@ impl Compiler {
     }
 
     fn fun_declaration(&mut self) {
-        let global = self.parse_variable("Excpect function name", TokenType::Fun);
+        let name = self.current.clone();
+        let global = self.parse_variable("Expect function name.", TokenType::Fun);
         self.mark_initialized();
-        self.function(FunctionType::Function);
+        self.consume(TokenType::LeftParen, "Expect '(' after function name.");
+
+        let name = self
+            .scanner
+            .borrow()
+            .lexeme(name.start, name.length)
+            .to_string();
+        let params = self.function(FunctionType::Function, name.clone(), false);
+        if self.scope_depth > 0 {
+            self.locals[self.local_count - 1].params = Some(params);
+        } else {
+            self.functions.insert(name, params);
+        }
         self.define_variable(global);
     }
 
-    fn function(&mut self, function_type: FunctionType) {
-        let scanner = Rc::clone(&self.scanner);
-        let objects = self.objects.as_ref().map(|o| Rc::clone(&o));
-        let function_name = self.scanner.borrow().lexeme(self.previous.start, self.previous.length);
-        let compiler = Compiler::new(scanner, objects, function_name, function_type, self.debug_print_code).compile_function();
-        let value = self.make_constant(Value::Function(compiler.function));
-        self.emit_byte(OpCode::Constant(value));
+    // Compiles `fun (a, b) { ... }` as an expression.
+    fn lambda(&mut self) {
+        let name = format!("anonymous@{}", self.previous.line);
+        self.consume(TokenType::LeftParen, "Expect '(' after 'fun'.");
+        self.function(FunctionType::Function, name, false);
+    }
+
+    // Whether the 'fun' at the start of a statement begins an anonymous function
+    // rather than a declaration.
+    fn lambda_ahead(&self) -> bool {
+        !self.lox_compat && self.peek_check(TokenType::LeftParen)
     }
 
-    fn compile_function(mut self) -> Self {
+    // Compiles the parameters and body of a function, which start after its '(',
+    // and emits the closure that creates the function at runtime. An arrow
+    // function's body is a single expression whose value it returns.
+    fn function(
+        &mut self,
+        function_type: FunctionType,
+        name: String,
+        arrow: bool,
+    ) -> Rc<Parameters> {
+        self.begin_function(function_type, name);
         self.begin_scope();
-        
-        self.consume(TokenType::LeftParen, "Expect '(' after function name.");
-        if !self.check(TokenType::LeftParen) {
+        self.parameters();
+        if arrow {
+            self.consume(TokenType::Arrow, "Expect '=>' after parameters.");
+            self.expression();
+            self.emit_byte(OpCode::Return);
+        } else {
+            self.consume(TokenType::LeftBrace, "Expect '{' before function body.");
+            self.block();
+            self.emit_return();
+        }
+
+        let function = match self.end_function() {
+            Some(function) => function,
+            None => return Rc::new(Parameters::default()),
+        };
+        let params = Rc::new(function.params.clone());
+        let index = self.make_constant(Value::Function(Rc::new(function)));
+        self.emit_byte(OpCode::Closure(index));
+        params
+    }
+
+    // Compiles the parameter list. Parameters with a default value follow the
+    // required ones, and a `...rest` parameter may come last.
+    fn parameters(&mut self) {
+        if !self.check(TokenType::RightParen) {
             loop {
-                self.function.arity += 1;
-                if self.function.arity > 255 {
+                if self.function.params.arity() == 255 {
                     self.error(
-                        "Can't have more than 255 parameters.", 
-                        self.previous.start,
-                        self.previous.length,
-                        self.previous.typ,
-                        self.previous.line
+                        "Can't have more than 255 parameters.",
+                        self.current.start,
+                        self.current.length,
+                        self.current.typ,
+                        self.current.line,
+                    );
+                }
+
+                if self.function.params.rest.is_some() {
+                    self.error(
+                        "Rest parameter must be the last parameter.",
+                        self.current.start,
+                        self.current.length,
+                        self.current.typ,
+                        self.current.line,
                     );
                 }
-                
-                let constant = self.parse_variable("Expect parameter name.", TokenType::Var);
-                self.define_variable(constant);
+
+                if self.match_and_advance(TokenType::DotDotDot) {
+                    self.rest_parameter();
+                } else {
+                    self.parameter();
+                }
 
                 if !self.match_and_advance(TokenType::Comma) {
                     break;
                 }
             }
         }
+
         self.consume(TokenType::RightParen, "Expect ')' after parameters.");
-        self.consume(TokenType::RightBrace, "Expect '{' before function body.");
-        
-        self.block();
-        self
+    }
+
+    fn parameter(&mut self) {
+        let name = self.current.clone();
+        let constant = self.parse_variable("Expect parameter name.", TokenType::Var);
+        let index = self.function.params.arity();
+        let after_default = self.function.params.has_defaults();
+        let lexeme = self
+            .scanner
+            .borrow()
+            .lexeme(name.start, name.length)
+            .to_string();
+        self.function.params.names.push(lexeme);
+
+        if !self.lox_compat && self.match_and_advance(TokenType::Equal) {
+            self.default_value(index);
+        } else if after_default {
+            self.error(
+                "Can't have a required parameter after one with a default value.",
+                name.start,
+                name.length,
+                name.typ,
+                name.line,
+            );
+        } else {
+            self.function.params.required += 1;
+        }
+
+        self.define_variable(constant);
+    }
+
+    // Compiles the default value of a parameter into the function's prologue,
+    // which evaluates it on each call that passes no argument for it.
+    fn default_value(&mut self, index: usize) {
+        let skip = self.emit_jump(OpCode::JumpIfGiven(index, 0));
+        self.expression();
+        self.emit_byte(OpCode::SetLocal(index));
+        self.emit_byte(OpCode::Pop);
+        self.patch_jump(skip);
+    }
+
+    fn rest_parameter(&mut self) {
+        let name = self.current.clone();
+        let constant = self.parse_variable("Expect parameter name after '...'.", TokenType::Var);
+        let lexeme = self
+            .scanner
+            .borrow()
+            .lexeme(name.start, name.length)
+            .to_string();
+        self.function.params.rest = Some(lexeme);
+        self.define_variable(constant);
+    }
+
+    // Sets this compiler aside as the enclosing one and continues parsing with a
+    // fresh compiler for the function.
+    fn begin_function(&mut self, function_type: FunctionType, name: String) {
+        let mut compiler = Compiler::new(
+            Rc::clone(&self.scanner),
+            self.objects.take(),
+            name,
+            function_type,
+            self.debug_print_code,
+        );
+        compiler.lox_compat = self.lox_compat;
+        compiler.module_name = self.module_name.clone();
+        compiler.arrow_parameters = mem::take(&mut self.arrow_parameters);
+        compiler.current = mem::replace(&mut self.current, Token::empty());
+        compiler.previous = mem::replace(&mut self.previous, Token::empty());
+        compiler.had_error = self.had_error;
+        compiler.panic_mode = self.panic_mode;
+
+        let enclosing = mem::replace(self, compiler);
+        self.enclosing = Some(Box::new(enclosing));
+    }
+
+    // Restores the enclosing compiler, handing the parser state back to it, and
+    // returns the function that was compiled.
+    fn end_function(&mut self) -> Option<FunctionObj> {
+        let enclosing = match self.enclosing.take() {
+            Some(enclosing) => *enclosing,
+            None => {
+                self.error(
+                    "Can't end a function that was never begun.",
+                    self.previous.start,
+                    self.previous.length,
+                    self.previous.typ,
+                    self.previous.line,
+                );
+                return None;
+            }
+        };
+
+        let compiler = mem::replace(self, enclosing);
+        self.current = compiler.current;
+        self.previous = compiler.previous;
+        self.objects = compiler.objects;
+        self.arrow_parameters = compiler.arrow_parameters;
+        self.had_error = self.had_error || compiler.had_error;
+        self.panic_mode = compiler.panic_mode;
+
+        if self.debug_print_code && !self.had_error {
+            let name = compiler.function.to_string();
+            compiler.function.chunk.disassemble(&name);
+        }
+
+        Some(compiler.function)
     }
 
     fn val_declaration(&mut self) {
@@ -208,7 +439,7 @@ impl Compiler {
 
         self.consume(
             TokenType::SemiColon,
-            "Expect ';' after variable declaration",
+            "Expect ';' after variable declaration.",
         );
         self.define_variable(global);
     }
@@ -225,27 +456,85 @@ impl Compiler {
         }
 
         if self.match_and_advance(TokenType::While) {
-            self.while_statement();
+            self.while_statement(None);
             return;
         }
 
         if self.match_and_advance(TokenType::For) {
-            self.for_statement();
+            self.for_statement(None);
+            return;
+        }
+
+        if self.match_and_advance(TokenType::Break) {
+            self.break_statement();
+            return;
+        }
+
+        if self.match_and_advance(TokenType::Continue) {
+            self.continue_statement();
+            return;
+        }
+
+        if self.match_and_advance(TokenType::Return) {
+            self.return_statement();
             return;
         }
 
-        if self.match_and_advance(TokenType::LeftBrace) {
+        if self.check(TokenType::LeftBrace) && !self.scanner.borrow().is_map_literal() {
+            self.advance();
             self.begin_scope();
             self.block();
             self.end_scope();
             return;
         }
 
+        if self.match_and_advance(TokenType::Try) {
+            self.try_statement();
+            return;
+        }
+
+        if self.match_and_advance(TokenType::Throw) {
+            self.throw_statement();
+            return;
+        }
+
+        if self.match_and_advance(TokenType::Yield) {
+            self.yield_statement();
+            return;
+        }
+
+        if self.match_and_advance(TokenType::Import) {
+            self.import_statement();
+            return;
+        }
+
+        if self.import_names_ahead() {
+            self.advance();
+            self.import_names_statement();
+            return;
+        }
+
         if self.match_and_advance(TokenType::Switch) {
             self.switch_statement();
             return;
         }
 
+        if self.match_and_advance(TokenType::Fallthrough) {
+            self.error(
+                "Can only use 'fallthrough' as the last statement of a case.",
+                self.previous.start,
+                self.previous.length,
+                self.previous.typ,
+                self.previous.line,
+            );
+            return;
+        }
+
+        if self.check(TokenType::Identifier) && self.peek_check(TokenType::Colon) {
+            self.labeled_statement();
+            return;
+        }
+
         self.expression_statement();
     }
 
@@ -267,7 +556,8 @@ impl Compiler {
         while self.local_count > 0
             && self.locals[self.local_count - 1].depth > self.scope_depth as i32
         {
-            self.emit_byte(OpCode::Pop);
+            self.emit_pop_local(self.local_count - 1);
+            self.locals.pop();
             self.local_count -= 1;
         }
     }
@@ -297,7 +587,31 @@ impl Compiler {
         self.patch_jump(else_jump);
     }
 
-    fn while_statement(&mut self) {
+    fn labeled_statement(&mut self) {
+        self.advance();
+        let label = self
+            .scanner
+            .borrow()
+            .lexeme(self.previous.start, self.previous.length)
+            .to_string();
+        self.consume(TokenType::Colon, "Expect ':' after label.");
+
+        if self.match_and_advance(TokenType::While) {
+            self.while_statement(Some(label));
+        } else if self.match_and_advance(TokenType::For) {
+            self.for_statement(Some(label));
+        } else {
+            self.error(
+                "Expect loop after label.",
+                self.current.start,
+                self.current.length,
+                self.current.typ,
+                self.current.line,
+            );
+        }
+    }
+
+    fn while_statement(&mut self, label: Option<String>) {
         let loop_start = self.current_chunk().count();
         self.consume(TokenType::LeftParen, "Expect '(' after 'while'.");
         self.expression();
@@ -305,19 +619,26 @@ impl Compiler {
 
         let exit_jump = self.emit_jump(OpCode::JumpIfFalse(0));
         self.emit_byte(OpCode::Pop);
+        self.begin_loop(label, loop_start);
         self.statement();
         self.emit_loop(loop_start);
 
         self.patch_jump(exit_jump);
         self.emit_byte(OpCode::Pop);
+        self.end_loop();
     }
 
-    fn for_statement(&mut self) {
+    fn for_statement(&mut self, label: Option<String>) {
         self.begin_scope();
         self.consume(TokenType::LeftParen, "Expect '(' after 'for'.");
+        if self.check(TokenType::Identifier) && self.peek_check(TokenType::In) {
+            self.for_in_statement(label);
+            self.end_scope();
+            return;
+        }
+
         if self.match_and_advance(TokenType::SemiColon) {
             // No initializer
-            ();
         } else if self.match_and_advance(TokenType::Var) {
             self.var_declaration();
         } else {
@@ -345,6 +666,7 @@ impl Compiler {
             self.patch_jump(body_jump);
         }
 
+        self.begin_loop(label, loop_start);
         self.statement();
         self.emit_loop(loop_start);
         if let Some(offset) = exit_jump {
@@ -352,164 +674,1098 @@ impl Compiler {
             self.emit_byte(OpCode::Pop);
         }
 
+        self.end_loop();
         self.end_scope();
     }
 
-    fn expression_statement(&mut self) {
-        self.expression();
-        self.consume(TokenType::SemiColon, "Expect ';' after expression.");
-        self.emit_byte(OpCode::Pop);
-    }
+    // Compiles `for (x in iterable)` after the '('. The iterable and the position
+    // in it live in hidden locals, and `x` is declared anew in each iteration so
+    // closures capture each value separately. A range `start..end step n` is
+    // stepped directly rather than built as a value.
+    fn for_in_statement(&mut self, label: Option<String>) {
+        self.advance();
+        let name = self.previous.clone();
+        self.advance();
 
-    fn switch_statement(&mut self) {
-        self.consume(TokenType::LeftParen, "Expect '(' after 'switch'.");
         self.expression();
-        self.consume(TokenType::RightParen, "Expect ')' after expression.");
-        self.consume(TokenType::LeftBrace, "Expect '{' after switch expression.");
-        self.begin_scope();
-
-        let mut jumps: Vec<usize> = Vec::new();
-        while self.match_and_advance(TokenType::Case) {
-            self.expression();
-            self.consume(TokenType::Colon, "Expect ':' after case expression.");
-            let case_jump = self.emit_jump(OpCode::Case(0));
-            self.emit_byte(OpCode::Pop);
-            self.emit_byte(OpCode::Pop);
-            while !self.check(TokenType::Case)
-                && !self.check(TokenType::Default)
-                && !self.check(TokenType::RightBrace)
-            {
-                self.declaration();
+        let hidden = if self.match_and_advance(TokenType::DotDot) {
+            Some(false)
+        } else if self.match_and_advance(TokenType::DotDotEqual) {
+            Some(true)
+        } else {
+            None
+        };
+        match hidden {
+            Some(_) => {
+                self.expression();
+                if self.check(TokenType::Identifier) && self.current_lexeme() == "step" {
+                    self.advance();
+                    self.expression();
+                } else {
+                    self.emit_constant(Value::Int(1));
+                }
+            }
+            None => {
+                self.emit_constant(Value::Int(0));
             }
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after for clauses.");
 
-            jumps.push(self.emit_jump(OpCode::Jump(0)));
-            self.emit_byte(OpCode::Pop);
-            self.patch_jump(case_jump);
+        let count = if hidden.is_some() { 3 } else { 2 };
+        for _ in 0..count {
+            self.add_local(Token::empty(), TokenType::Val);
+            self.mark_initialized();
         }
 
-        self.default_statement();
-        self.end_scope();
-        self.consume(TokenType::RightBrace, "Expect '}' after switch block.");
+        let slot = self.local_count - 1 - count;
+        let loop_start = self.current_chunk().count();
+        let exit_jump = self.emit_jump(match hidden {
+            Some(false) => OpCode::ForRange(slot, 0),
+            Some(true) => OpCode::ForRangeInclusive(slot, 0),
+            None => OpCode::ForIn(slot, 0),
+        });
 
-        for jump in jumps {
-            self.patch_jump(jump);
-        }
+        self.begin_loop(label, loop_start);
+        self.begin_scope();
+        self.add_local(name, TokenType::Var);
+        self.mark_initialized();
+        self.statement();
+        self.end_scope();
+        self.emit_loop(loop_start);
+        self.patch_jump(exit_jump);
+        self.end_loop();
     }
 
-    fn default_statement(&mut self) {
-        if self.match_and_advance(TokenType::Default) {
-            self.consume(TokenType::Colon, "Expect ':' after 'default'");
-            self.emit_byte(OpCode::Pop);
-            while !self.check(TokenType::RightBrace) {
-                self.declaration();
-            }
-        }
+    fn previous_lexeme(&self) -> String {
+        self.scanner
+            .borrow()
+            .lexeme(self.previous.start, self.previous.length)
+            .to_string()
     }
 
-    fn expression(&mut self) {
-        self.parse_precedence(Precedence::Assignment);
+    fn current_lexeme(&self) -> String {
+        self.scanner
+            .borrow()
+            .lexeme(self.current.start, self.current.length)
+            .to_string()
     }
 
-    fn parse_precedence(&mut self, precedence: Precedence) {
-        self.advance();
-        let can_assign = precedence <= Precedence::Assignment;
-        let prefix_rule = self.prefix_rule(self.previous.typ, can_assign);
-        match prefix_rule {
-            Some(rule) => rule(self),
-            None => {
-                self.error(
-                    "Expect expression",
-                    self.previous.start,
-                    self.previous.length,
-                    self.previous.typ,
-                    self.previous.line,
-                );
-            }
-        }
+    fn begin_loop(&mut self, label: Option<String>, start: usize) {
+        let context = LoopContext::new(label, start, self.scope_depth, self.tries.len());
+        self.loops.push(context);
+    }
 
-        while precedence <= self.current.typ.precedence() {
-            self.advance();
-            if let Some(rule) = self.infix_rule(self.previous.typ) {
-                rule(self);
+    fn end_loop(&mut self) {
+        if let Some(context) = self.loops.pop() {
+            for jump in context.breaks {
+                self.patch_jump(jump);
             }
         }
+    }
 
-        if !can_assign && self.match_and_advance(TokenType::Equal) {
-            self.error(
-                "Invalid assignment target",
-                self.previous.start,
-                self.previous.length,
-                self.previous.typ,
-                self.previous.line,
-            );
+    fn break_statement(&mut self) {
+        let context = self.enclosing_loop("break");
+        self.consume(TokenType::SemiColon, "Expect ';' after 'break'.");
+        if let Some(index) = context {
+            self.emit_exit(TryExit::Break(index));
         }
     }
 
-    fn parse_variable(&mut self, error_msg: &str, variable_type: TokenType) -> usize {
-        self.consume(TokenType::Identifier, error_msg);
-        if self.declare_local_variable(variable_type) {
-            return 0;
+    fn continue_statement(&mut self) {
+        let context = self.enclosing_loop("continue");
+        self.consume(TokenType::SemiColon, "Expect ';' after 'continue'.");
+        if let Some(index) = context {
+            self.emit_exit(TryExit::Continue(index));
         }
-
-        let (index, _) = self.identifier_constant();
-        index
     }
 
-    fn declare_local_variable(&mut self, variable_type: TokenType) -> bool {
-        if self.scope_depth == 0 {
-            return false;
-        }
+    // Leaves the loop or function, after running the `finally` clauses of the
+    // `try` statements it leaves. Control goes to the innermost such clause,
+    // which carries on with the exit once it has run. A returned value is on
+    // the stack.
+    fn emit_exit(&mut self, exit: TryExit) {
+        let try_depth = match exit {
+            TryExit::Return => 0,
+            TryExit::Break(index) | TryExit::Continue(index) => self.loops[index].try_depth,
+        };
 
-        let name = mem::replace(&mut self.previous, Token::empty());
-        for i in (0..self.local_count).rev() {
-            if self.locals[i].depth != -1 && self.locals[i].depth < self.scope_depth as i32 {
-                break;
+        for index in (try_depth..self.tries.len()).rev() {
+            for _ in 0..self.tries[index].handlers() {
+                self.emit_byte(OpCode::PopHandler);
             }
 
-            if self.identifiers_equal(&self.locals[i].name, &name) {
-                self.error(
-                    "Already a variable with this name in this scope.",
-                    self.locals[i].name.start,
-                    self.locals[i].name.length,
-                    self.locals[i].name.typ,
-                    self.locals[i].name.line,
-                );
+            if self.tries[index].runs_finally() {
+                self.enter_finally(index, exit);
+                return;
             }
         }
 
-        self.add_local(name, variable_type);
-        true
+        match exit {
+            TryExit::Return => self.emit_byte(OpCode::Return),
+            TryExit::Break(index) => {
+                self.pop_loop_locals(index);
+                let jump = self.emit_jump(OpCode::Jump(0));
+                self.loops[index].breaks.push(jump);
+            }
+            TryExit::Continue(index) => {
+                self.pop_loop_locals(index);
+                let start = self.loops[index].start;
+                self.emit_loop(start);
+            }
+        }
     }
 
-    fn identifiers_equal(&self, a: &Token, b: &Token) -> bool {
-        if a.length != b.length {
-            return false;
+    // Jumps to the `finally` clause of the `try` statement at `index`, noting
+    // the exit it should carry on with.
+    fn enter_finally(&mut self, index: usize, exit: TryExit) {
+        let slot = self.tries[index].slot;
+        if let TryExit::Return = exit {
+            self.emit_byte(OpCode::SetLocal(slot));
+            self.emit_byte(OpCode::Pop);
         }
 
-        let res = self.scanner.borrow_mut().lexeme(a.start, a.length) == self.scanner.borrow_mut().lexeme(b.start, b.length);
-        res
-    }
-
-    fn add_local(&mut self, name: Token, variable_type: TokenType) {
-        let local = Local::new(name, variable_type);
-        self.locals.push(local);
-        self.local_count += 1;
-    }
+        let depth = self.tries[index].scope_depth as i32;
+        let mut count = self.local_count;
+        while count > 0 && self.locals[count - 1].depth > depth {
+            self.emit_pop_local(count - 1);
+            count -= 1;
+        }
 
-    fn and(&mut self) {
-        let end_jump = self.emit_jump(OpCode::JumpIfFalse(0));
+        let code = EXIT + self.tries[index].exits.len() as f64;
+        self.tries[index].exits.push(exit);
+        self.emit_constant(Value::Number(code));
+        self.emit_byte(OpCode::SetLocal(slot + 1));
         self.emit_byte(OpCode::Pop);
-        self.parse_precedence(Precedence::And);
-        self.patch_jump(end_jump);
+        let jump = self.emit_jump(OpCode::Jump(0));
+        self.tries[index].entries.push(jump);
     }
 
-    fn or(&mut self) {
-        let else_jump = self.emit_jump(OpCode::JumpIfFalse(0));
-        let end_jump = self.emit_jump(OpCode::Jump(0));
-
-        self.patch_jump(else_jump);
-        self.emit_byte(OpCode::Pop);
-        self.parse_precedence(Precedence::Or);
+    // Compiles `try { } catch (e) { } finally { }`, where either clause can be
+    // left out but not both. The `catch` clause runs with the error in `e`, and
+    // the `finally` clause however control leaves the others.
+    fn try_statement(&mut self) {
+        let has_finally = self.scanner.borrow().try_has_finally();
+        self.begin_scope();
+        let slot = if has_finally {
+            self.emit_byte(OpCode::Nil);
+            self.emit_constant(Value::Number(COMPLETED));
+            for _ in 0..2 {
+                self.add_local(Token::empty(), TokenType::Val);
+                self.mark_initialized();
+            }
+            self.local_count - 3
+        } else {
+            0
+        };
+
+        self.tries
+            .push(TryContext::new(self.scope_depth, slot, has_finally));
+        let handler = self.emit_jump(OpCode::Try(0));
+        self.consume(TokenType::LeftBrace, "Expect '{' after 'try'.");
+        self.begin_scope();
+        self.block();
+        self.end_scope();
+        self.emit_byte(OpCode::PopHandler);
+
+        let mut finished = vec![self.emit_jump(OpCode::Jump(0))];
+        self.patch_jump(handler);
+        if self.match_and_advance(TokenType::Catch) {
+            self.catch_clause(has_finally, &mut finished);
+        } else if !has_finally {
+            self.error(
+                "Expect 'catch' or 'finally' after try block.",
+                self.current.start,
+                self.current.length,
+                self.current.typ,
+                self.current.line,
+            );
+        }
+
+        if has_finally {
+            // An error escaped the other clauses and is on the stack.
+            self.emit_byte(OpCode::SetLocal(slot));
+            self.emit_byte(OpCode::Pop);
+            self.emit_constant(Value::Number(THROWN));
+            self.emit_byte(OpCode::SetLocal(slot + 1));
+            self.emit_byte(OpCode::Pop);
+            self.finally_clause(finished);
+        } else {
+            self.tries.pop();
+            for jump in finished {
+                self.patch_jump(jump);
+            }
+        }
+
+        self.end_scope();
+    }
+
+    fn catch_clause(&mut self, has_finally: bool, finished: &mut Vec<usize>) {
+        if let Some(context) = self.tries.last_mut() {
+            context.region = TryRegion::Catch;
+        }
+
+        let handler = has_finally.then(|| self.emit_jump(OpCode::Try(0)));
+        self.begin_scope();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'catch'.");
+        self.consume(TokenType::Identifier, "Expect error variable name.");
+        let name = self.previous.clone();
+        self.add_local(name, TokenType::Var);
+        self.mark_initialized();
+        self.consume(TokenType::RightParen, "Expect ')' after error variable.");
+        self.consume(TokenType::LeftBrace, "Expect '{' after catch clause.");
+        self.block();
+        self.end_scope();
+
+        if let Some(handler) = handler {
+            self.emit_byte(OpCode::PopHandler);
+            finished.push(self.emit_jump(OpCode::Jump(0)));
+            self.patch_jump(handler);
+        }
+    }
+
+    // Compiles the `finally` clause, which every way out of the statement goes
+    // through, then carries on the way control was leaving.
+    fn finally_clause(&mut self, finished: Vec<usize>) {
+        let entries = match self.tries.last_mut() {
+            Some(context) => {
+                context.region = TryRegion::Finally;
+                mem::take(&mut context.entries)
+            }
+            None => return,
+        };
+        for jump in finished.into_iter().chain(entries) {
+            self.patch_jump(jump);
+        }
+
+        self.consume(TokenType::Finally, "Expect 'finally' after try block.");
+        self.consume(TokenType::LeftBrace, "Expect '{' after 'finally'.");
+        self.begin_scope();
+        self.block();
+        self.end_scope();
+
+        let context = match self.tries.pop() {
+            Some(context) => context,
+            None => return,
+        };
+        let skip = self.emit_completion_check(context.slot, THROWN);
+        self.emit_byte(OpCode::GetLocal(context.slot));
+        self.emit_byte(OpCode::Throw);
+        self.patch_completion_check(skip);
+
+        for (index, exit) in context.exits.into_iter().enumerate() {
+            let skip = self.emit_completion_check(context.slot, EXIT + index as f64);
+            if let TryExit::Return = exit {
+                self.emit_byte(OpCode::GetLocal(context.slot));
+            }
+            self.emit_exit(exit);
+            self.patch_completion_check(skip);
+        }
+    }
+
+    // Skips the code that follows unless the `finally` clause was reached the
+    // way `code` stands for.
+    fn emit_completion_check(&mut self, slot: usize, code: f64) -> usize {
+        self.emit_byte(OpCode::GetLocal(slot + 1));
+        self.emit_constant(Value::Number(code));
+        self.emit_byte(OpCode::Equal);
+        let skip = self.emit_jump(OpCode::JumpIfFalse(0));
+        self.emit_byte(OpCode::Pop);
+        skip
+    }
+
+    fn patch_completion_check(&mut self, skip: usize) {
+        self.patch_jump(skip);
+        self.emit_byte(OpCode::Pop);
+    }
+
+    fn throw_statement(&mut self) {
+        self.expression();
+        self.consume(TokenType::SemiColon, "Expect ';' after thrown value.");
+        self.emit_byte(OpCode::Throw);
+    }
+
+    // Compiles `yield value;`, which makes the function around it a generator.
+    fn yield_statement(&mut self) {
+        if self.function_type == FunctionType::Script {
+            self.error(
+                "Can't yield from top-level code.",
+                self.previous.start,
+                self.previous.length,
+                self.previous.typ,
+                self.previous.line,
+            );
+        }
+
+        if self.match_and_advance(TokenType::SemiColon) {
+            self.emit_byte(OpCode::Nil);
+        } else {
+            self.expression();
+            self.consume(TokenType::SemiColon, "Expect ';' after yielded value.");
+        }
+
+        self.function.generator = true;
+        self.emit_byte(OpCode::Yield);
+    }
+
+    // Compiles `import "path" as name;`, which binds the module's namespace to
+    // a global.
+    fn import_statement(&mut self) {
+        self.module_path();
+        self.consume_contextual("as", "Expect 'as' after module path.");
+        let global = self.parse_variable("Expect module name after 'as'.", TokenType::Val);
+        self.consume(TokenType::SemiColon, "Expect ';' after import.");
+        self.define_variable(global);
+    }
+
+    // Compiles `from "path" import a, b;`, which binds each of the names to the
+    // module global of the same name.
+    fn import_names_statement(&mut self) {
+        self.module_path();
+        self.consume(TokenType::Import, "Expect 'import' after module path.");
+        loop {
+            let global = self.parse_variable("Expect name to import.", TokenType::Val);
+            let name = self.previous_lexeme();
+            let index = self.make_constant(Value::Str(name));
+            self.emit_byte(OpCode::ImportName(index));
+            self.define_variable(global);
+            if !self.match_and_advance(TokenType::Comma) {
+                break;
+            }
+        }
+
+        self.consume(TokenType::SemiColon, "Expect ';' after imported names.");
+        self.emit_byte(OpCode::Pop);
+    }
+
+    // Whether the statement starts with the contextual keyword `from` followed by
+    // a module path.
+    fn import_names_ahead(&self) -> bool {
+        !self.lox_compat
+            && self.check(TokenType::Identifier)
+            && self.current_lexeme() == "from"
+            && self.peek_check(TokenType::Str)
+    }
+
+    // Compiles the path of an import, which must be a plain string, and emits
+    // the instruction that pushes the module's namespace.
+    fn module_path(&mut self) {
+        let keyword = self.previous.clone();
+        if self.scope_depth > 0 || self.function_type != FunctionType::Script {
+            self.error(
+                "Can only import at the top level.",
+                keyword.start,
+                keyword.length,
+                keyword.typ,
+                keyword.line,
+            );
+        }
+
+        self.consume(TokenType::Str, "Expect module path string.");
+        let path = match self.previous.literal.clone() {
+            Some(literal) => literal,
+            None => self.previous_lexeme(),
+        };
+        let index = self.make_constant(Value::Str(path));
+        self.emit_byte(OpCode::Import(index));
+    }
+
+    fn consume_contextual(&mut self, keyword: &str, msg: &str) {
+        if self.check(TokenType::Identifier) && self.current_lexeme() == keyword {
+            self.advance();
+            return;
+        }
+
+        self.error(
+            msg,
+            self.current.start,
+            self.current.length,
+            self.current.typ,
+            self.current.line,
+        );
+    }
+
+    // Finds the loop targeted by a break or continue, either the innermost one or
+    // the one named by the optional label that follows the keyword.
+    fn enclosing_loop(&mut self, keyword: &str) -> Option<usize> {
+        if self.loops.is_empty() {
+            let msg = format!("Can't use '{}' outside of a loop.", keyword);
+            self.error(
+                &msg,
+                self.previous.start,
+                self.previous.length,
+                self.previous.typ,
+                self.previous.line,
+            );
+            return None;
+        }
+
+        if !self.match_and_advance(TokenType::Identifier) {
+            return Some(self.loops.len() - 1);
+        }
+
+        let label = self
+            .scanner
+            .borrow()
+            .lexeme(self.previous.start, self.previous.length)
+            .to_string();
+        let index = self
+            .loops
+            .iter()
+            .rposition(|context| context.label.as_ref() == Some(&label));
+        if index.is_none() {
+            let msg = format!("No enclosing loop labeled '{}'.", label);
+            self.error(
+                &msg,
+                self.previous.start,
+                self.previous.length,
+                self.previous.typ,
+                self.previous.line,
+            );
+        }
+
+        index
+    }
+
+    // Pops the locals declared inside a loop body without ending their scopes, so
+    // a jump out of the body leaves the stack as the loop found it.
+    fn pop_loop_locals(&mut self, index: usize) {
+        let depth = self.loops[index].scope_depth as i32;
+        let mut count = self.local_count;
+        while count > 0 && self.locals[count - 1].depth > depth {
+            self.emit_pop_local(count - 1);
+            count -= 1;
+        }
+    }
+
+    // Captured locals are closed over rather than simply popped.
+    fn emit_pop_local(&mut self, index: usize) {
+        if self.locals[index].is_captured {
+            self.emit_byte(OpCode::CloseUpvalue);
+        } else {
+            self.emit_byte(OpCode::Pop);
+        }
+    }
+
+    fn return_statement(&mut self) {
+        if self.function_type == FunctionType::Script {
+            self.error(
+                "Can't return from top-level code.",
+                self.previous.start,
+                self.previous.length,
+                self.previous.typ,
+                self.previous.line,
+            );
+        }
+
+        if self.match_and_advance(TokenType::SemiColon) {
+            self.emit_byte(OpCode::Nil);
+        } else {
+            self.expression();
+            self.consume(TokenType::SemiColon, "Expect ';' after return value.");
+        }
+        self.emit_exit(TryExit::Return);
+    }
+
+    fn expression_statement(&mut self) {
+        self.expression();
+        self.consume(TokenType::SemiColon, "Expect ';' after expression.");
+        self.emit_byte(OpCode::Pop);
+    }
+
+    fn switch_statement(&mut self) {
+        self.switch_subject();
+
+        let mut constants = vec![];
+        let mut end_jumps = vec![];
+        let mut fallthrough: Option<(usize, Token)> = None;
+        while self.match_and_advance(TokenType::Case) {
+            let next_case = self.case_labels(&mut constants);
+            self.emit_byte(OpCode::Pop);
+            if let Some((jump, _)) = fallthrough.take() {
+                self.patch_jump(jump);
+            }
+
+            self.begin_scope();
+            let fallthrough_token = self.case_body();
+            self.end_scope();
+
+            match fallthrough_token {
+                Some(token) => fallthrough = Some((self.emit_jump(OpCode::Jump(0)), token)),
+                None => end_jumps.push(self.emit_jump(OpCode::Jump(0))),
+            }
+            self.patch_jump(next_case);
+        }
+
+        // No case matched, so the subject is still on the stack.
+        self.emit_byte(OpCode::Pop);
+        if self.match_and_advance(TokenType::Default) {
+            if let Some((jump, _)) = fallthrough.take() {
+                self.patch_jump(jump);
+            }
+
+            self.consume(TokenType::Colon, "Expect ':' after 'default'.");
+            self.begin_scope();
+            if let Some(token) = self.case_body() {
+                self.error(
+                    "Can't fall through from the last case.",
+                    token.start,
+                    token.length,
+                    token.typ,
+                    token.line,
+                );
+            }
+            self.end_scope();
+        }
+
+        if let Some((_, token)) = fallthrough {
+            self.error(
+                "Can't fall through from the last case.",
+                token.start,
+                token.length,
+                token.typ,
+                token.line,
+            );
+        }
+
+        self.consume(TokenType::RightBrace, "Expect '}' after switch block.");
+        for jump in end_jumps {
+            self.patch_jump(jump);
+        }
+    }
+
+    fn switch_expression(&mut self) {
+        let switch_token = self.previous.clone();
+        self.switch_subject();
+
+        let mut constants = vec![];
+        let mut end_jumps = vec![];
+        while self.match_and_advance(TokenType::Case) {
+            let next_case = self.case_labels(&mut constants);
+            self.emit_byte(OpCode::Pop);
+            self.expression();
+            end_jumps.push(self.emit_jump(OpCode::Jump(0)));
+            self.patch_jump(next_case);
+            self.switch_arm_end();
+        }
+
+        self.emit_byte(OpCode::Pop);
+        if self.match_and_advance(TokenType::Default) {
+            self.consume(TokenType::Colon, "Expect ':' after 'default'.");
+            self.expression();
+            self.switch_arm_end();
+        } else {
+            self.warning(
+                "Switch expression has no 'default' case.",
+                switch_token.start,
+                switch_token.length,
+                switch_token.line,
+            );
+            self.emit_byte(OpCode::Nil);
+        }
+
+        self.consume(TokenType::RightBrace, "Expect '}' after switch expression.");
+        for jump in end_jumps {
+            self.patch_jump(jump);
+        }
+    }
+
+    fn switch_subject(&mut self) {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'switch'.");
+        self.expression();
+        self.consume(TokenType::RightParen, "Expect ')' after expression.");
+        self.consume(TokenType::LeftBrace, "Expect '{' after switch expression.");
+    }
+
+    fn switch_arm_end(&mut self) {
+        if !self.check(TokenType::RightBrace) {
+            self.consume(TokenType::SemiColon, "Expect ';' after case value.");
+        }
+    }
+
+    // Compiles the comma separated labels of a case into tests against the switch
+    // subject, which stays on the stack. A matching label jumps to the case body,
+    // and the returned jump is taken when none of them match.
+    fn case_labels(&mut self, constants: &mut Vec<Value>) -> usize {
+        let mut matched = vec![];
+        loop {
+            let label = self.current.clone();
+            let code_start = self.current_chunk().count();
+            self.expression();
+            if self.match_and_advance(TokenType::DotDot) {
+                self.expression();
+                matched.push(self.emit_jump(OpCode::CaseRange(0)));
+            } else if self.match_and_advance(TokenType::DotDotEqual) {
+                self.expression();
+                matched.push(self.emit_jump(OpCode::CaseRangeInclusive(0)));
+            } else {
+                self.check_duplicate_case(code_start, &label, constants);
+                matched.push(self.emit_jump(OpCode::Case(0)));
+            }
+
+            if !self.match_and_advance(TokenType::Comma) {
+                break;
+            }
+        }
+
+        self.consume(TokenType::Colon, "Expect ':' after case expression.");
+        let next_case = self.emit_jump(OpCode::Jump(0));
+        for jump in matched {
+            self.patch_jump(jump);
+        }
+
+        next_case
+    }
+
+    // Returns the 'fallthrough' token when the case ends by falling through into
+    // the next one.
+    fn case_body(&mut self) -> Option<Token> {
+        while !self.check(TokenType::Case)
+            && !self.check(TokenType::Default)
+            && !self.check(TokenType::RightBrace)
+            && !self.check(TokenType::Eof)
+        {
+            if self.match_and_advance(TokenType::Fallthrough) {
+                let token = self.previous.clone();
+                self.consume(TokenType::SemiColon, "Expect ';' after 'fallthrough'.");
+                if self.check(TokenType::Case)
+                    || self.check(TokenType::Default)
+                    || self.check(TokenType::RightBrace)
+                {
+                    return Some(token);
+                }
+
+                self.error(
+                    "Can only use 'fallthrough' as the last statement of a case.",
+                    token.start,
+                    token.length,
+                    token.typ,
+                    token.line,
+                );
+            }
+
+            self.declaration();
+        }
+
+        None
+    }
+
+    // Warns when a case repeats a constant from an earlier case, since the later
+    // one can never match.
+    fn check_duplicate_case(
+        &mut self,
+        code_start: usize,
+        label: &Token,
+        constants: &mut Vec<Value>,
+    ) {
+        let constant = |index: usize| self.function.chunk.constants.borrow().get(index);
+        let value = match &self.function.chunk.code[code_start..] {
+            [OpCode::Constant(index)] => constant(*index),
+            [OpCode::Constant(index), OpCode::Negate] => match constant(*index) {
+                Value::Int(n) => match n.checked_neg() {
+                    Some(n) => Value::Int(n),
+                    None => return,
+                },
+                Value::Number(n) => Value::Number(-n),
+                _ => return,
+            },
+            [OpCode::True] => Value::Bool(true),
+            [OpCode::False] => Value::Bool(false),
+            [OpCode::Nil] => Value::Nil,
+            _ => return,
+        };
+
+        if constants.iter().any(|c| c.equals(&value)) {
+            let length = self.previous.start + self.previous.length - label.start;
+            self.warning("Duplicate case value.", label.start, length, label.line);
+        } else {
+            constants.push(value);
+        }
+    }
+
+    fn expression(&mut self) {
+        self.parse_precedence(Precedence::Assignment);
+    }
+
+    fn parse_precedence(&mut self, precedence: Precedence) {
+        self.advance();
+        let start = self.current_chunk().count();
+        let can_assign = precedence <= Precedence::Assignment;
+        let prefix_rule = self.prefix_rule(self.previous.typ, can_assign);
+        match prefix_rule {
+            Some(rule) => rule(self),
+            None => {
+                self.error(
+                    "Expect expression.",
+                    self.previous.start,
+                    self.previous.length,
+                    self.previous.typ,
+                    self.previous.line,
+                );
+            }
+        }
+
+        while precedence <= self.current.typ.precedence() {
+            self.advance();
+            if let Some(rule) = self.infix_rule(self.previous.typ, can_assign) {
+                self.operand_start = start;
+                rule(self);
+            }
+        }
+
+        if self.match_and_advance(TokenType::PlusPlus)
+            || self.match_and_advance(TokenType::MinusMinus)
+        {
+            self.error(
+                "Invalid increment target.",
+                self.previous.start,
+                self.previous.length,
+                self.previous.typ,
+                self.previous.line,
+            );
+        } else if !can_assign && self.current.typ.is_assignment() {
+            self.advance();
+            self.error(
+                "Invalid assignment target.",
+                self.previous.start,
+                self.previous.length,
+                self.previous.typ,
+                self.previous.line,
+            );
+        }
+    }
+
+    fn parse_variable(&mut self, error_msg: &str, variable_type: TokenType) -> usize {
+        self.consume(TokenType::Identifier, error_msg);
+        let name = self
+            .scanner
+            .borrow()
+            .lexeme(self.previous.start, self.previous.length)
+            .to_string();
+        if self.scope_depth == 0 {
+            self.functions.remove(&name);
+        }
+
+        if let Some(text) = self.pending_doc.take() {
+            let keyword = variable_type.to_string().to_lowercase();
+            let doc = DocComment::new(keyword, name, self.previous.line, text);
+            self.function.docs.push(doc);
+        }
+
+        if self.declare_local_variable(variable_type) {
+            return 0;
+        }
+
+        let (index, _) = self.identifier_constant(variable_type);
+        index
+    }
+
+    fn declare_local_variable(&mut self, variable_type: TokenType) -> bool {
+        if self.scope_depth == 0 {
+            return false;
+        }
+
+        let name = mem::replace(&mut self.previous, Token::empty());
+        for i in (0..self.local_count).rev() {
+            if self.locals[i].depth != -1 && self.locals[i].depth < self.scope_depth as i32 {
+                break;
+            }
+
+            if self.identifiers_equal(&self.locals[i].name, &name) {
+                self.error(
+                    "Already a variable with this name in this scope.",
+                    self.locals[i].name.start,
+                    self.locals[i].name.length,
+                    self.locals[i].name.typ,
+                    self.locals[i].name.line,
+                );
+            }
+        }
+
+        self.add_local(name, variable_type);
+        true
+    }
+
+    fn identifiers_equal(&self, a: &Token, b: &Token) -> bool {
+        if a.length != b.length {
+            return false;
+        }
+
+        let scanner = self.scanner.borrow();
+        scanner.lexeme(a.start, a.length) == scanner.lexeme(b.start, b.length)
+    }
+
+    fn add_local(&mut self, name: Token, variable_type: TokenType) {
+        let local = Local::new(name, variable_type);
+        self.locals.push(local);
+        self.local_count += 1;
+    }
+
+    // Compiles `target.name`, which reads a global of a module.
+    fn dot(&mut self, can_assign: bool) {
+        self.consume(TokenType::Identifier, "Expect property name after '.'.");
+        let name = self.previous_lexeme();
+        let index = self.make_constant(Value::Str(name));
+        self.emit_byte(OpCode::GetProperty(index));
+        if can_assign && self.current.typ.is_assignment() {
+            self.advance();
+            self.error(
+                "Can't assign to a module's globals.",
+                self.previous.start,
+                self.previous.length,
+                self.previous.typ,
+                self.previous.line,
+            );
+        }
+    }
+
+    // Compiles `target?.name`, which is nil when the target is nil. The calls,
+    // subscripts and property reads chained after it are skipped as well.
+    fn question_dot(&mut self, can_assign: bool) {
+        let read_jump = self.emit_jump(OpCode::JumpIfNotNil(0));
+        let end_jump = self.emit_jump(OpCode::Jump(0));
+        self.patch_jump(read_jump);
+        self.dot(can_assign);
+        while Precedence::Call <= self.current.typ.precedence() {
+            self.advance();
+            if let Some(rule) = self.infix_rule(self.previous.typ, can_assign) {
+                rule(self);
+            }
+        }
+        self.patch_jump(end_jump);
+    }
+
+    // Compiles a call. Named arguments are always matched to parameters by name
+    // at runtime, since the variable may be rebound to another function. When
+    // the callee is a function declared earlier with `fun`, mistakes in them are
+    // warned about here, as the rebinding may happen anywhere.
+    fn call(&mut self) {
+        let known = match self.known_callee.take() {
+            Some((offset, params))
+                if offset == self.operand_start && self.current_chunk().count() == offset + 1 =>
+            {
+                Some(params)
+            }
+            _ => None,
+        };
+
+        let (count, names) = self.argument_list();
+        if names.is_empty() {
+            self.emit_byte(OpCode::Call(count));
+            return;
+        }
+
+        let positional = count - names.len();
+        if let Some(Err(msg)) = known.map(|params| params.resolve(positional, &names)) {
+            self.warning(
+                &msg,
+                self.previous.start,
+                self.previous.length,
+                self.previous.line,
+            );
+        }
+
+        let names = names.into_iter().map(Value::Str).collect();
+        let index = self.make_constant(Value::list(names));
+        self.emit_byte(OpCode::CallNamed(count, index));
+    }
+
+    // Returns the number of arguments and the names of the named ones, which
+    // come after the positional ones.
+    fn argument_list(&mut self) -> (usize, Vec<String>) {
+        let mut count = 0;
+        let mut names = vec![];
+        if !self.check(TokenType::RightParen) {
+            loop {
+                if self.check(TokenType::Identifier) && self.peek_check(TokenType::Colon) {
+                    self.advance();
+                    let name = self
+                        .scanner
+                        .borrow()
+                        .lexeme(self.previous.start, self.previous.length)
+                        .to_string();
+                    names.push(name);
+                    self.advance();
+                } else if !names.is_empty() {
+                    self.error(
+                        "Positional argument can't follow a named argument.",
+                        self.current.start,
+                        self.current.length,
+                        self.current.typ,
+                        self.current.line,
+                    );
+                }
+
+                self.expression();
+                if count == 255 {
+                    self.error(
+                        "Can't have more than 255 arguments.",
+                        self.previous.start,
+                        self.previous.length,
+                        self.previous.typ,
+                        self.previous.line,
+                    );
+                }
+                count += 1;
+
+                if !self.match_and_advance(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+
+        self.consume(TokenType::RightParen, "Expect ')' after arguments.");
+        (count, names)
+    }
+
+    fn list(&mut self) {
+        let mut count = 0;
+        while !self.check(TokenType::RightBracket) && !self.check(TokenType::Eof) {
+            self.expression();
+            count += 1;
+
+            if !self.match_and_advance(TokenType::Comma) {
+                break;
+            }
+        }
+
+        self.consume(TokenType::RightBracket, "Expect ']' after list elements.");
+        self.emit_byte(OpCode::List(count));
+    }
+
+    // Compiles `{"a": 1, b: 2}`. A bare name as a key stands for the string of
+    // its name, so a key held in a variable is written in parentheses.
+    fn map(&mut self) {
+        let mut count = 0;
+        while !self.check(TokenType::RightBrace) && !self.check(TokenType::Eof) {
+            if self.check(TokenType::Identifier) && self.peek_check(TokenType::Colon) {
+                self.advance();
+                let name = self
+                    .scanner
+                    .borrow()
+                    .lexeme(self.previous.start, self.previous.length)
+                    .to_string();
+                self.emit_constant(Value::Str(name));
+            } else {
+                self.expression();
+            }
+            self.consume(TokenType::Colon, "Expect ':' after map key.");
+            self.expression();
+            count += 1;
+
+            if !self.match_and_advance(TokenType::Comma) {
+                break;
+            }
+        }
+
+        self.consume(TokenType::RightBrace, "Expect '}' after map entries.");
+        self.emit_byte(OpCode::Map(count));
+    }
+
+    // Compiles `xs[i]`, an assignment to it, or the slice `xs[start:end]`, where
+    // either bound may be left out.
+    fn subscript(&mut self, can_assign: bool) {
+        if self.check(TokenType::Colon) {
+            self.emit_byte(OpCode::Nil);
+        } else {
+            self.expression();
+        }
+
+        if self.match_and_advance(TokenType::Colon) {
+            if self.check(TokenType::RightBracket) {
+                self.emit_byte(OpCode::Nil);
+            } else {
+                self.expression();
+            }
+            self.consume(TokenType::RightBracket, "Expect ']' after slice.");
+            self.emit_byte(OpCode::Slice);
+            return;
+        }
+
+        self.consume(TokenType::RightBracket, "Expect ']' after index.");
+        if can_assign && self.match_and_advance(TokenType::Equal) {
+            self.expression();
+            self.emit_byte(OpCode::SetIndex);
+            return;
+        }
+
+        if let Some(op) = self.compound_assignment_op(self.current.typ) {
+            if can_assign {
+                self.advance();
+                self.emit_byte(OpCode::Dup2);
+                self.emit_byte(OpCode::GetIndex);
+                self.expression();
+                self.emit_byte(op);
+                self.emit_byte(OpCode::SetIndex);
+                return;
+            }
+        }
+
+        if self.match_and_advance(TokenType::PlusPlus)
+            || self.match_and_advance(TokenType::MinusMinus)
+        {
+            // The old value is set beneath the list and index, and stays on the
+            // stack as the result of `xs[i]++`.
+            self.emit_byte(OpCode::Dup2);
+            self.emit_byte(OpCode::GetIndex);
+            self.emit_byte(OpCode::Rotate);
+            self.increment_index(self.previous.typ);
+            self.emit_byte(OpCode::Pop);
+            return;
+        }
+
+        self.emit_byte(OpCode::GetIndex);
+    }
+
+    // Adds or subtracts one from the element whose list or map and index are on
+    // the stack, leaving the updated value.
+    fn increment_index(&mut self, operator: TokenType) {
+        self.emit_byte(OpCode::Dup2);
+        self.emit_byte(OpCode::GetIndex);
+        self.emit_constant(Value::Int(1));
+        self.emit_byte(self.increment_op(operator));
+        self.emit_byte(OpCode::SetIndex);
+    }
+
+    fn and(&mut self) {
+        let end_jump = self.emit_jump(OpCode::JumpIfFalse(0));
+        self.emit_byte(OpCode::Pop);
+        self.parse_precedence(Precedence::And);
+        self.patch_jump(end_jump);
+    }
+
+    fn or(&mut self) {
+        let else_jump = self.emit_jump(OpCode::JumpIfFalse(0));
+        let end_jump = self.emit_jump(OpCode::Jump(0));
+
+        self.patch_jump(else_jump);
+        self.emit_byte(OpCode::Pop);
+        self.parse_precedence(Precedence::Or);
+        self.patch_jump(end_jump);
+    }
+
+    // Compiles `cond ? a : b`. The else branch may be a conditional itself, so
+    // `a ? b : c ? d : e` groups to the right.
+    fn conditional(&mut self) {
+        let else_jump = self.emit_jump(OpCode::JumpIfFalse(0));
+        self.emit_byte(OpCode::Pop);
+        self.parse_precedence(Precedence::Assignment);
+        self.consume(
+            TokenType::Colon,
+            "Expect ':' after then branch of conditional.",
+        );
+
+        let end_jump = self.emit_jump(OpCode::Jump(0));
+        self.patch_jump(else_jump);
+        self.emit_byte(OpCode::Pop);
+        self.parse_precedence(Precedence::Conditional);
+        self.patch_jump(end_jump);
+    }
+
+    // Compiles `a ?? b`, which only evaluates `b` when `a` is nil.
+    fn coalesce(&mut self) {
+        let end_jump = self.emit_jump(OpCode::JumpIfNotNil(0));
+        self.emit_byte(OpCode::Pop);
+        self.parse_precedence(Precedence::Coalesce.next());
         self.patch_jump(end_jump);
     }
 
@@ -529,39 +1785,84 @@ impl Compiler {
         self.locals[self.local_count - 1].depth = self.scope_depth as i32;
     }
 
-    fn identifier_constant(&mut self) -> (usize, TokenType) {
+    // Finds or adds the constant naming a global. A global not seen before
+    // takes `new_type`, which is `var` for one that is only being used.
+    fn identifier_constant(&mut self, new_type: TokenType) -> (usize, TokenType) {
         let lexeme = self
             .scanner
             .borrow()
-            .lexeme(self.previous.start, self.previous.length);
+            .lexeme(self.previous.start, self.previous.length)
+            .to_string();
         match self.current_chunk().find_identifier(&lexeme) {
             Some((index, value)) => match value {
                 Value::ValIdent(_) => (index, TokenType::Val),
                 _ => (index, TokenType::Var),
             },
             None => {
-                let value = match self.declaration_start {
+                let dec_type = match self.enclosing_global(&lexeme) {
+                    Some(Value::ValIdent(_)) => TokenType::Val,
+                    Some(_) => TokenType::Var,
+                    None => new_type,
+                };
+                let value = match dec_type {
                     TokenType::Val => Value::ValIdent(lexeme),
                     _ => Value::VarIdent(lexeme),
                 };
-                (self.make_constant(value), self.declaration_start)
+                (self.make_constant(value), dec_type)
             }
         }
     }
 
+    // Looks up a global already named by one of the enclosing functions, so a
+    // function can't reassign a `val` declared outside of it.
+    fn enclosing_global(&self, name: &str) -> Option<Value> {
+        let enclosing = self.enclosing.as_ref()?;
+        match enclosing.function.chunk.find_identifier(name) {
+            Some((_, value)) => Some(value),
+            None => enclosing.enclosing_global(name),
+        }
+    }
+
     fn grouping(&mut self) {
+        let open = self.previous.start;
+        if !self.arrow_parameters.contains_key(&open) {
+            let scanner = self.scanner.borrow();
+            scanner.find_arrow_parameters(open, &self.current, &mut self.arrow_parameters);
+        }
+
+        if self.arrow_parameters.remove(&open).unwrap_or(false) {
+            let name = format!("anonymous@{}", self.previous.line);
+            self.function(FunctionType::Function, name, true);
+            return;
+        }
+
         self.expression();
-        self.consume(TokenType::RightParen, "Expect ')' after expression");
+        self.consume(TokenType::RightParen, "Expect ')' after expression.");
     }
 
     fn binary(&mut self) {
         let operator_type = self.previous.typ;
-        self.parse_precedence(self.previous.typ.precedence().next());
+        if operator_type == TokenType::StarStar {
+            // Exponentiation is right associative and its right operand may be
+            // negated, as in `2 ** -1`.
+            self.parse_precedence(Precedence::Unary);
+        } else {
+            self.parse_precedence(self.previous.typ.precedence().next());
+        }
+
         match operator_type {
             TokenType::Plus => self.emit_byte(OpCode::Add),
             TokenType::Minus => self.emit_byte(OpCode::Subtract),
             TokenType::Star => self.emit_byte(OpCode::Multiply),
             TokenType::Slash => self.emit_byte(OpCode::Divide),
+            TokenType::Percent => self.emit_byte(OpCode::Modulo),
+            TokenType::TildeSlash => self.emit_byte(OpCode::IntDivide),
+            TokenType::StarStar => self.emit_byte(OpCode::Power),
+            TokenType::Ampersand => self.emit_byte(OpCode::BitAnd),
+            TokenType::Pipe => self.emit_byte(OpCode::BitOr),
+            TokenType::Caret => self.emit_byte(OpCode::BitXor),
+            TokenType::LessLess => self.emit_byte(OpCode::ShiftLeft),
+            TokenType::GreaterGreater => self.emit_byte(OpCode::ShiftRight),
             TokenType::EqualEqual => self.emit_byte(OpCode::Equal),
             TokenType::BangEqual => self.emit_bytes(OpCode::Equal, OpCode::Not),
             TokenType::Greater => self.emit_byte(OpCode::Greater),
@@ -578,26 +1879,60 @@ impl Compiler {
         match operator_type {
             TokenType::Minus => self.emit_byte(OpCode::Negate),
             TokenType::Bang => self.emit_byte(OpCode::Not),
+            TokenType::Tilde => self.emit_byte(OpCode::BitNot),
             _ => (),
         };
     }
 
+    // A literal without a decimal point or exponent is an integer. Reference Lox
+    // only has floats.
     fn number(&mut self) {
-        let number = self
-            .scanner
-            .borrow()
-            .lexeme(self.previous.start, self.previous.length);
-        let value = Value::Number(number.parse::<f64>().unwrap());
-        self.emit_constant(value);
+        let number = match self.previous.literal.take() {
+            Some(literal) => literal,
+            None => self
+                .scanner
+                .borrow()
+                .lexeme(self.previous.start, self.previous.length)
+                .to_string(),
+        };
+
+        let is_integer = !self.lox_compat && !number.contains(['.', 'e', 'E']);
+        let value = if is_integer {
+            number
+                .parse::<i64>()
+                .map(Value::Int)
+                .map_err(|_| "Integer literal is too large.")
+        } else {
+            number
+                .parse::<f64>()
+                .map(Value::Number)
+                .map_err(|_| "Invalid number literal.")
+        };
+
+        match value {
+            Ok(value) => {
+                self.emit_constant(value);
+            }
+            Err(msg) => self.error(
+                msg,
+                self.previous.start,
+                self.previous.length,
+                self.previous.typ,
+                self.previous.line,
+            ),
+        }
     }
 
     fn string(&mut self) {
         let next_obj = self.objects.take();
-        let string = Value::SourceStr(SourceStr::new(
-            self.previous.start,
-            self.previous.length,
-            Rc::clone(&self.scanner.borrow().source),
-        ));
+        let string = match self.previous.literal.take() {
+            Some(literal) => Value::Str(literal),
+            None => Value::SourceStr(SourceStr::new(
+                self.previous.start,
+                self.previous.length,
+                Rc::clone(&self.scanner.borrow().source),
+            )),
+        };
 
         let value = self.emit_constant(string);
         self.objects = Some(Rc::new(Obj::new(
@@ -607,24 +1942,201 @@ impl Compiler {
         )));
     }
 
+    // Compiles a string with embedded `${expr}`s. The scanner hands over the
+    // literal text before each expression as an Interpolation token and the text
+    // after the last one as a Str, and each part is stringified and concatenated
+    // onto the ones before it.
+    fn interpolation(&mut self) {
+        let mut has_part = false;
+        loop {
+            self.interpolation_segment(&mut has_part);
+            self.expression();
+            if self.match_and_advance(TokenType::FormatSpec) {
+                self.format_spec();
+            } else {
+                self.emit_byte(OpCode::Stringify);
+            }
+
+            if has_part {
+                self.emit_byte(OpCode::Add);
+            }
+            has_part = true;
+
+            if !self.match_and_advance(TokenType::Interpolation) {
+                break;
+            }
+        }
+
+        self.consume(TokenType::Str, "Expect '}' after interpolation.");
+        self.interpolation_segment(&mut has_part);
+    }
+
+    fn interpolation_segment(&mut self, has_part: &mut bool) {
+        if self.previous.length == 0 && self.previous.literal.is_none() {
+            return;
+        }
+
+        self.string();
+        if *has_part {
+            self.emit_byte(OpCode::Add);
+        }
+        *has_part = true;
+    }
+
+    fn format_spec(&mut self) {
+        let spec = self
+            .scanner
+            .borrow()
+            .lexeme(self.previous.start, self.previous.length)
+            .to_string();
+        if FormatSpec::parse(&spec).is_none() {
+            let msg = format!("Invalid format specifier '{}'.", spec);
+            self.error(
+                &msg,
+                self.previous.start,
+                self.previous.length,
+                self.previous.typ,
+                self.previous.line,
+            );
+        }
+
+        let index = self.make_constant(Value::Str(spec));
+        self.emit_byte(OpCode::Format(index));
+    }
+
     fn variable(&mut self, can_assign: bool) {
         self.named_variable(can_assign);
     }
 
     fn named_variable(&mut self, can_assign: bool) {
-        let arg = self.resolve_local();
-        let (get_op, set_op, dec_type) = match arg {
-            Some((index, dec_type)) => (OpCode::GetLocal(index), OpCode::SetLocal(index), dec_type),
+        let name = self.previous.clone();
+        let (get_op, set_op) = self.resolve_variable(self.current.typ);
+
+        if can_assign && self.match_and_advance(TokenType::Equal) {
+            self.forget_function(&name);
+            self.expression();
+            self.emit_byte(set_op);
+            return;
+        }
+
+        if let Some(op) = self.compound_assignment_op(self.current.typ) {
+            if can_assign {
+                self.forget_function(&name);
+                self.advance();
+                self.emit_byte(get_op);
+                self.expression();
+                self.emit_byte(op);
+                self.emit_byte(set_op);
+                return;
+            }
+        }
+
+        if self.match_and_advance(TokenType::PlusPlus)
+            || self.match_and_advance(TokenType::MinusMinus)
+        {
+            // The old value stays on the stack as the result of `x++`.
+            self.emit_byte(get_op);
+            self.emit_byte(get_op);
+            self.emit_constant(Value::Int(1));
+            self.emit_byte(self.increment_op(self.previous.typ));
+            self.emit_byte(set_op);
+            self.emit_byte(OpCode::Pop);
+            return;
+        }
+
+        let offset = self.current_chunk().count();
+        self.emit_byte(get_op);
+        self.known_callee = self.known_function(&name).map(|params| (offset, params));
+    }
+
+    // The parameters of the function a `fun` declaration bound to the variable,
+    // unless it has been assigned since.
+    fn known_function(&self, name: &Token) -> Option<Rc<Parameters>> {
+        if let Some(index) = self.find_local(name) {
+            return self.locals[index].params.clone();
+        }
+
+        match &self.enclosing {
+            Some(enclosing) => enclosing.known_function(name),
+            None => {
+                let scanner = self.scanner.borrow();
+                self.functions
+                    .get(scanner.lexeme(name.start, name.length))
+                    .cloned()
+            }
+        }
+    }
+
+    fn forget_function(&mut self, name: &Token) {
+        if let Some(index) = self.find_local(name) {
+            self.locals[index].params = None;
+            return;
+        }
+
+        match self.enclosing.as_mut() {
+            Some(enclosing) => enclosing.forget_function(name),
             None => {
-                let (index, dec_type) = self.identifier_constant();
-                (OpCode::GetGlobal(index), OpCode::SetGlobal(index), dec_type)
+                let lexeme = self
+                    .scanner
+                    .borrow()
+                    .lexeme(name.start, name.length)
+                    .to_string();
+                self.functions.remove(&lexeme);
             }
+        }
+    }
+
+    // Compiles `++x` and `--x`, which evaluate to the updated value. The target
+    // may also be an element, as in `++xs[i]` or `--grid[i][j]`.
+    fn prefix_increment(&mut self) {
+        let operator = self.previous.typ;
+        let msg = if operator == TokenType::PlusPlus {
+            "Expect variable name after '++'."
+        } else {
+            "Expect variable name after '--'."
         };
+        self.consume(TokenType::Identifier, msg);
+
+        if self.check(TokenType::LeftBracket) {
+            let (get_op, _) = self.resolve_variable(TokenType::LeftBracket);
+            self.emit_byte(get_op);
+            while self.match_and_advance(TokenType::LeftBracket) {
+                self.expression();
+                self.consume(TokenType::RightBracket, "Expect ']' after index.");
+                if self.check(TokenType::LeftBracket) {
+                    self.emit_byte(OpCode::GetIndex);
+                }
+            }
+            self.increment_index(operator);
+            return;
+        }
+
+        let (get_op, set_op) = self.resolve_variable(operator);
+        self.emit_byte(get_op);
+        self.emit_constant(Value::Int(1));
+        self.emit_byte(self.increment_op(operator));
+        self.emit_byte(set_op);
+    }
 
-        if dec_type == TokenType::Val
-            && self.declaration_start != TokenType::Val
-            && self.check(TokenType::Equal)
+    // Finds the get and set instructions for the variable just consumed, and
+    // reports an error when the operator after it would assign to a `val`.
+    fn resolve_variable(&mut self, operator: TokenType) -> (OpCode, OpCode) {
+        let name = self.previous.clone();
+        let (get_op, set_op, dec_type) = if let Some((index, dec_type)) = self.resolve_local(&name)
         {
+            (OpCode::GetLocal(index), OpCode::SetLocal(index), dec_type)
+        } else if let Some((index, dec_type)) = self.resolve_upvalue(&name) {
+            (
+                OpCode::GetUpvalue(index),
+                OpCode::SetUpvalue(index),
+                dec_type,
+            )
+        } else {
+            let (index, dec_type) = self.identifier_constant(TokenType::Var);
+            (OpCode::GetGlobal(index), OpCode::SetGlobal(index), dec_type)
+        };
+
+        if dec_type == TokenType::Val && operator.is_assignment() {
             self.error(
                 "Cannot reassign to value.",
                 self.previous.start,
@@ -634,33 +2146,91 @@ impl Compiler {
             );
         }
 
-        if can_assign && self.match_and_advance(TokenType::Equal) {
-            self.expression();
-            self.emit_byte(set_op);
-            return;
+        (get_op, set_op)
+    }
+
+    fn compound_assignment_op(&self, typ: TokenType) -> Option<OpCode> {
+        match typ {
+            TokenType::PlusEqual => Some(OpCode::Add),
+            TokenType::MinusEqual => Some(OpCode::Subtract),
+            TokenType::StarEqual => Some(OpCode::Multiply),
+            TokenType::SlashEqual => Some(OpCode::Divide),
+            TokenType::PercentEqual => Some(OpCode::Modulo),
+            _ => None,
         }
+    }
 
-        self.emit_byte(get_op);
+    fn increment_op(&self, typ: TokenType) -> OpCode {
+        if typ == TokenType::PlusPlus {
+            OpCode::Add
+        } else {
+            OpCode::Subtract
+        }
     }
 
-    fn resolve_local(&mut self) -> Option<(usize, TokenType)> {
-        for i in (0..self.local_count).rev() {
-            if self.identifiers_equal(&self.locals[i].name, &self.previous) {
-                if self.locals[i].depth == -1 {
-                    self.error(
-                        "Can't read local variable in its own initializer.",
-                        self.previous.start,
-                        self.previous.length,
-                        self.previous.typ,
-                        self.previous.line,
-                    );
-                }
+    fn resolve_local(&mut self, name: &Token) -> Option<(usize, TokenType)> {
+        let index = self.find_local(name)?;
+        if self.locals[index].depth == -1 {
+            self.error(
+                "Can't read local variable in its own initializer.",
+                name.start,
+                name.length,
+                name.typ,
+                name.line,
+            );
+        }
+
+        Some((index - 1, self.locals[index].dec_type))
+    }
+
+    fn find_local(&self, name: &Token) -> Option<usize> {
+        (0..self.local_count)
+            .rev()
+            .find(|&i| self.identifiers_equal(&self.locals[i].name, name))
+    }
 
-                return Some((i - 1, (self.locals[i].dec_type)));
+    // Resolves a variable declared in one of the enclosing functions, capturing
+    // it in each function between there and here.
+    fn resolve_upvalue(&mut self, name: &Token) -> Option<(usize, TokenType)> {
+        let enclosing = self.enclosing.as_mut()?;
+        let (capture, dec_type, initialized) = match enclosing.find_local(name) {
+            Some(index) => {
+                let local = &mut enclosing.locals[index];
+                local.is_captured = true;
+                (
+                    Capture::new(true, index - 1),
+                    local.dec_type,
+                    local.depth != -1,
+                )
+            }
+            None => {
+                let (index, dec_type) = enclosing.resolve_upvalue(name)?;
+                (Capture::new(false, index), dec_type, true)
             }
+        };
+
+        if !initialized {
+            self.error(
+                "Can't read local variable in its own initializer.",
+                name.start,
+                name.length,
+                name.typ,
+                name.line,
+            );
         }
 
-        None
+        Some((self.add_upvalue(capture), dec_type))
+    }
+
+    fn add_upvalue(&mut self, capture: Capture) -> usize {
+        let captures = &mut self.function.captures;
+        match captures.iter().position(|c| *c == capture) {
+            Some(index) => index,
+            None => {
+                captures.push(capture);
+                captures.len() - 1
+            }
+        }
     }
 
     fn literal(&mut self) {
@@ -684,20 +2254,36 @@ impl Compiler {
         }
     }
 
-    fn prefix_rule(
-        &mut self,
-        typ: TokenType,
-        can_assign: bool,
-    ) -> Option<Box<dyn Fn(&mut Compiler)>> {
+    fn prefix_rule(&mut self, typ: TokenType, can_assign: bool) -> Option<ParseFn> {
         match typ {
             TokenType::LeftParen => Some(Box::new(|compiler: &mut Compiler| compiler.grouping())),
             TokenType::Minus => Some(Box::new(|compiler: &mut Compiler| compiler.unary())),
             TokenType::Number => Some(Box::new(|compiler: &mut Compiler| compiler.number())),
             TokenType::Str => Some(Box::new(|compiler: &mut Compiler| compiler.string())),
+            TokenType::Interpolation => {
+                Some(Box::new(|compiler: &mut Compiler| compiler.interpolation()))
+            }
+            TokenType::LeftBracket => Some(Box::new(|compiler: &mut Compiler| compiler.list())),
+            TokenType::LeftBrace if !self.lox_compat => {
+                Some(Box::new(|compiler: &mut Compiler| compiler.map()))
+            }
             TokenType::True | TokenType::False | TokenType::Nil => {
                 Some(Box::new(|compiler: &mut Compiler| compiler.literal()))
             }
-            TokenType::Bang => Some(Box::new(|compiler: &mut Compiler| compiler.unary())),
+            TokenType::Bang | TokenType::Tilde => {
+                Some(Box::new(|compiler: &mut Compiler| compiler.unary()))
+            }
+            TokenType::PlusPlus | TokenType::MinusMinus => {
+                Some(Box::new(|compiler: &mut Compiler| {
+                    compiler.prefix_increment()
+                }))
+            }
+            TokenType::Switch => Some(Box::new(|compiler: &mut Compiler| {
+                compiler.switch_expression()
+            })),
+            TokenType::Fun if !self.lox_compat => {
+                Some(Box::new(|compiler: &mut Compiler| compiler.lambda()))
+            }
             TokenType::Identifier => Some(Box::new(move |compiler: &mut Compiler| {
                 compiler.variable(can_assign)
             })),
@@ -705,27 +2291,48 @@ impl Compiler {
         }
     }
 
-    fn infix_rule(&mut self, typ: TokenType) -> Option<Box<dyn Fn(&mut Compiler)>> {
+    fn infix_rule(&mut self, typ: TokenType, can_assign: bool) -> Option<ParseFn> {
         match typ {
             TokenType::Minus
             | TokenType::Plus
             | TokenType::Slash
             | TokenType::Star
+            | TokenType::Percent
+            | TokenType::TildeSlash
+            | TokenType::StarStar
+            | TokenType::Ampersand
+            | TokenType::Pipe
+            | TokenType::Caret
+            | TokenType::LessLess
+            | TokenType::GreaterGreater
             | TokenType::BangEqual
             | TokenType::EqualEqual
             | TokenType::Greater
             | TokenType::GreaterEqual
             | TokenType::Less
             | TokenType::LessEqual => Some(Box::new(|compiler: &mut Compiler| compiler.binary())),
+            TokenType::LeftParen => Some(Box::new(|compiler: &mut Compiler| compiler.call())),
+            TokenType::LeftBracket => Some(Box::new(move |compiler: &mut Compiler| {
+                compiler.subscript(can_assign)
+            })),
+            TokenType::Dot => Some(Box::new(move |compiler: &mut Compiler| {
+                compiler.dot(can_assign)
+            })),
+            TokenType::QuestionDot => Some(Box::new(move |compiler: &mut Compiler| {
+                compiler.question_dot(can_assign)
+            })),
             TokenType::And => Some(Box::new(|compiler: &mut Compiler| compiler.and())),
             TokenType::Or => Some(Box::new(|compiler: &mut Compiler| compiler.or())),
+            TokenType::Question => Some(Box::new(|compiler: &mut Compiler| compiler.conditional())),
+            TokenType::QuestionQuestion => {
+                Some(Box::new(|compiler: &mut Compiler| compiler.coalesce()))
+            }
             _ => None,
         }
     }
 
-    #[allow(dead_code)]
     fn emit_return(&mut self) {
-        self.emit_byte(OpCode::Return);
+        self.emit_bytes(OpCode::Nil, OpCode::Return);
     }
 
     fn emit_constant(&mut self, value: Value) -> usize {
@@ -735,11 +2342,29 @@ impl Compiler {
     }
 
     fn patch_jump(&mut self, offset: usize) {
-        let jump = self.current_chunk().count() - offset;
+        let jump = self.current_chunk().count() - offset - 1;
         match self.current_chunk().code[offset] {
             OpCode::Jump(_) => self.current_chunk().code[offset] = OpCode::Jump(jump),
             OpCode::JumpIfFalse(_) => self.current_chunk().code[offset] = OpCode::JumpIfFalse(jump),
+            OpCode::JumpIfNotNil(_) => {
+                self.current_chunk().code[offset] = OpCode::JumpIfNotNil(jump)
+            }
+            OpCode::JumpIfGiven(param, _) => {
+                self.current_chunk().code[offset] = OpCode::JumpIfGiven(param, jump)
+            }
             OpCode::Case(_) => self.current_chunk().code[offset] = OpCode::Case(jump),
+            OpCode::CaseRange(_) => self.current_chunk().code[offset] = OpCode::CaseRange(jump),
+            OpCode::CaseRangeInclusive(_) => {
+                self.current_chunk().code[offset] = OpCode::CaseRangeInclusive(jump)
+            }
+            OpCode::Try(_) => self.current_chunk().code[offset] = OpCode::Try(jump),
+            OpCode::ForIn(slot, _) => self.current_chunk().code[offset] = OpCode::ForIn(slot, jump),
+            OpCode::ForRange(slot, _) => {
+                self.current_chunk().code[offset] = OpCode::ForRange(slot, jump)
+            }
+            OpCode::ForRangeInclusive(slot, _) => {
+                self.current_chunk().code[offset] = OpCode::ForRangeInclusive(slot, jump)
+            }
             _ => self.error(
                 "Unrecognized jump operation",
                 self.previous.start,
@@ -758,7 +2383,7 @@ impl Compiler {
 
     fn emit_jump(&mut self, byte: OpCode) -> usize {
         self.emit_byte(byte);
-        self.current_chunk().count()
+        self.current_chunk().count() - 1
     }
 
     fn emit_loop(&mut self, loop_start: usize) {
@@ -788,6 +2413,13 @@ impl Compiler {
         self.current.typ == typ
     }
 
+    fn peek_check(&self, typ: TokenType) -> bool {
+        match self.scanner.borrow_mut().peek_token() {
+            Ok(token) => token.typ == typ,
+            Err(_) => false,
+        }
+    }
+
     fn synchronize(&mut self) {
         self.panic_mode = false;
         while self.current.typ != TokenType::Eof {
@@ -804,6 +2436,10 @@ impl Compiler {
                 | TokenType::If
                 | TokenType::While
                 | TokenType::Print
+                | TokenType::Try
+                | TokenType::Throw
+                | TokenType::Yield
+                | TokenType::Import
                 | TokenType::Return => return,
                 _ => self.advance(),
             }
@@ -818,15 +2454,40 @@ impl Compiler {
         self.panic_mode = true;
         self.had_error = true;
 
-        let lexeme = self.scanner.borrow().lexeme(start, length);
-        Self::error_at(lexeme, msg, typ, line)
+        let scanner = self.scanner.borrow();
+        self.error_at(scanner.lexeme(start, length), msg, typ, line)
+    }
+
+    fn warning(&mut self, msg: &str, start: usize, length: usize, line: usize) {
+        if self.panic_mode {
+            return;
+        }
+
+        let scanner = self.scanner.borrow();
+        let lexeme = scanner.lexeme(start, length);
+        eprintln!(
+            "[line {}] Warning{} at '{}': {}",
+            line,
+            self.in_module(),
+            lexeme,
+            msg
+        );
     }
 
-    fn error_at(lexeme: String, msg: &str, typ: TokenType, line: usize) {
+    fn error_at(&self, lexeme: &str, msg: &str, typ: TokenType, line: usize) {
+        let module = self.in_module();
         match typ {
-            TokenType::Eof => println!("[line {}] Error at end: {}", line, msg),
-            TokenType::Error => println!("[line {}] Error: {}", line, msg),
-            _ => println!("[line {}] Error at '{}': {}", line, lexeme, msg),
+            TokenType::Eof => eprintln!("[line {}] Error{} at end: {}", line, module, msg),
+            TokenType::Error => eprintln!("[line {}] Error{}: {}", line, module, msg),
+            _ => eprintln!("[line {}] Error{} at '{}': {}", line, module, lexeme, msg),
         };
     }
+
+    // Names the module in errors, which for the script would only be noise.
+    fn in_module(&self) -> String {
+        match &self.module_name {
+            Some(name) => format!(" in '{}'", name),
+            None => String::new(),
+        }
+    }
 }
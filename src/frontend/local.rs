@@ -1,3 +1,7 @@
+use std::rc::Rc;
+
+use crate::backend::parameters::Parameters;
+
 use super::token::Token;
 use super::token_type::TokenType;
 
@@ -6,6 +10,11 @@ pub struct Local {
     pub name: Token,
     pub depth: i32,
     pub dec_type: TokenType,
+    // Whether a closure captures the local, which must then be moved off the
+    // stack when it goes out of scope.
+    pub is_captured: bool,
+    // The parameters of the function a `fun` declaration bound to the local.
+    pub params: Option<Rc<Parameters>>,
 }
 
 impl Local {
@@ -14,6 +23,8 @@ impl Local {
             name,
             depth: -1,
             dec_type,
+            is_captured: false,
+            params: None,
         }
     }
 }
@@ -0,0 +1,25 @@
+pub struct LoopContext {
+    pub label: Option<String>,
+    pub start: usize,
+    pub scope_depth: usize,
+    pub breaks: Vec<usize>,
+    // How many `try` statements were open when the loop began.
+    pub try_depth: usize,
+}
+
+impl LoopContext {
+    pub fn new(
+        label: Option<String>,
+        start: usize,
+        scope_depth: usize,
+        try_depth: usize,
+    ) -> LoopContext {
+        LoopContext {
+            label,
+            start,
+            scope_depth,
+            breaks: vec![],
+            try_depth,
+        }
+    }
+}
@@ -1,6 +1,8 @@
 pub mod compiler;
 mod local;
+mod loop_context;
 mod precedence;
 pub mod scanner;
 mod token;
 mod token_type;
+mod try_context;
@@ -1,58 +1,224 @@
 use super::token::Token;
 use super::token_type::TokenType;
 
-use std::rc::Rc;
+use std::{collections::HashMap, rc::Rc};
 
-#[derive(Debug)]
+use unicode_xid::UnicodeXID;
+
+// An expression embedded in a string with `${`, which the scanner is inside of.
+#[derive(Debug, Clone)]
+struct OpenInterpolation {
+    start: usize,
+    line: usize,
+    depth: usize,
+    // Conditional expressions whose ':' hasn't been reached yet, so that a ':'
+    // can be told apart from the start of a format specifier.
+    conditionals: usize,
+}
+
+// Scans the UTF-8 bytes of the source in place. Token positions are byte
+// offsets, and anything outside of ASCII only matters inside identifiers and
+// string literals.
+#[derive(Debug, Clone)]
 pub struct Scanner {
-    pub source: Rc<Vec<char>>,
+    pub source: Rc<str>,
     start: usize,
     current: usize,
     line: usize,
+    lox_compat: bool,
+    interpolations: Vec<OpenInterpolation>,
+    pending_error: Option<Token>,
+    pending_doc: Option<String>,
 }
 
 impl Scanner {
-    pub fn new(source: String) -> Self {
+    pub fn new(source: String, lox_compat: bool) -> Self {
         Self {
-            source: Rc::new(source.chars().collect()),
+            source: Rc::from(source),
             start: 0,
             current: 0,
             line: 1,
+            lox_compat,
+            interpolations: vec![],
+            pending_error: None,
+            pending_doc: None,
         }
     }
 
     pub fn scan_token(&mut self) -> Result<Token, Token> {
-        self.skip_whitespace_and_comments();
+        if let Some(error) = self.pending_error.take() {
+            return Err(error);
+        }
+
+        self.skip_whitespace_and_comments()?;
+
+        let doc = self.pending_doc.take();
+        let mut token = self.next_token()?;
+        token.doc = doc;
+        Ok(token)
+    }
 
+    fn next_token(&mut self) -> Result<Token, Token> {
         self.start = self.current;
         if self.is_at_end() {
+            if let Some(error) = self.unterminated_interpolation() {
+                return Err(error);
+            }
             return Ok(self.make_token(TokenType::Eof));
         }
 
-        let c = self.advance();
-        if c.is_alphabetic() {
+        let c = self.peek_char();
+        self.current += c.len_utf8();
+        if c == 'r' && !self.lox_compat && self.peek_is(b'"') {
+            self.advance();
+            return self.string_token(true);
+        }
+
+        if self.is_identifier_start(c) {
             return Ok(self.identifier_token());
         }
 
-        if c.is_digit(10) {
-            return Ok(self.number_token());
+        if c.is_ascii_digit() {
+            return self.number_token();
         }
 
         match c {
-            '(' => Ok(self.make_token(TokenType::LeftParen)),
-            ')' => Ok(self.make_token(TokenType::RightParen)),
-            '{' => Ok(self.make_token(TokenType::LeftBrace)),
-            '}' => Ok(self.make_token(TokenType::RightBrace)),
+            '(' | '{' | '[' if c != '[' || !self.lox_compat => {
+                if let Some(interpolation) = self.interpolations.last_mut() {
+                    interpolation.depth += 1;
+                }
+
+                let typ = match c {
+                    '(' => TokenType::LeftParen,
+                    '{' => TokenType::LeftBrace,
+                    _ => TokenType::LeftBracket,
+                };
+                Ok(self.make_token(typ))
+            }
+            ')' => {
+                self.close_nesting();
+                Ok(self.make_token(TokenType::RightParen))
+            }
+            ']' if !self.lox_compat => {
+                self.close_nesting();
+                Ok(self.make_token(TokenType::RightBracket))
+            }
+            '}' => {
+                if self.interpolations.last().is_some_and(|i| i.depth == 0) {
+                    // The embedded expression is over, so the string carries on.
+                    self.interpolations.pop();
+                    self.start = self.current;
+                    return self.string_body(false, false);
+                }
+
+                self.close_nesting();
+                Ok(self.make_token(TokenType::RightBrace))
+            }
             ';' => Ok(self.make_token(TokenType::SemiColon)),
-            ':' => Ok(self.make_token(TokenType::Colon)),
+            ':' if self.interpolations.last().is_some_and(|i| i.depth == 0) => {
+                match self.interpolations.last_mut() {
+                    Some(interpolation) if interpolation.conditionals > 0 => {
+                        interpolation.conditionals -= 1;
+                        Ok(self.make_token(TokenType::Colon))
+                    }
+                    _ => Ok(self.format_spec_token()),
+                }
+            }
+            ':' if !self.lox_compat => Ok(self.make_token(TokenType::Colon)),
             ',' => Ok(self.make_token(TokenType::Comma)),
-            '.' => Ok(self.make_token(TokenType::Dot)),
-            '-' => Ok(self.make_token(TokenType::Minus)),
-            '+' => Ok(self.make_token(TokenType::Plus)),
-            '/' => Ok(self.make_token(TokenType::Slash)),
-            '*' => Ok(self.make_token(TokenType::Star)),
+            '.' => {
+                let typ = if self.lox_compat || !self.check(b'.') {
+                    TokenType::Dot
+                } else if self.check(b'.') {
+                    TokenType::DotDotDot
+                } else if self.check(b'=') {
+                    TokenType::DotDotEqual
+                } else {
+                    TokenType::DotDot
+                };
+                Ok(self.make_token(typ))
+            }
+            '-' => {
+                let typ = if self.lox_compat {
+                    TokenType::Minus
+                } else if self.check(b'-') {
+                    TokenType::MinusMinus
+                } else if self.check(b'=') {
+                    TokenType::MinusEqual
+                } else {
+                    TokenType::Minus
+                };
+                Ok(self.make_token(typ))
+            }
+            '+' => {
+                let typ = if self.lox_compat {
+                    TokenType::Plus
+                } else if self.check(b'+') {
+                    TokenType::PlusPlus
+                } else if self.check(b'=') {
+                    TokenType::PlusEqual
+                } else {
+                    TokenType::Plus
+                };
+                Ok(self.make_token(typ))
+            }
+            '/' => {
+                let typ = if !self.lox_compat && self.check(b'=') {
+                    TokenType::SlashEqual
+                } else {
+                    TokenType::Slash
+                };
+                Ok(self.make_token(typ))
+            }
+            '*' => {
+                let typ = if self.lox_compat {
+                    TokenType::Star
+                } else if self.check(b'*') {
+                    TokenType::StarStar
+                } else if self.check(b'=') {
+                    TokenType::StarEqual
+                } else {
+                    TokenType::Star
+                };
+                Ok(self.make_token(typ))
+            }
+            '?' if !self.lox_compat => {
+                if self.check(b'?') {
+                    return Ok(self.make_token(TokenType::QuestionQuestion));
+                }
+
+                if self.check(b'.') {
+                    return Ok(self.make_token(TokenType::QuestionDot));
+                }
+
+                if let Some(interpolation) = self.interpolations.last_mut() {
+                    if interpolation.depth == 0 {
+                        interpolation.conditionals += 1;
+                    }
+                }
+                Ok(self.make_token(TokenType::Question))
+            }
+            '%' if !self.lox_compat => {
+                let typ = if self.check(b'=') {
+                    TokenType::PercentEqual
+                } else {
+                    TokenType::Percent
+                };
+                Ok(self.make_token(typ))
+            }
+            '&' if !self.lox_compat => Ok(self.make_token(TokenType::Ampersand)),
+            '|' if !self.lox_compat => Ok(self.make_token(TokenType::Pipe)),
+            '^' if !self.lox_compat => Ok(self.make_token(TokenType::Caret)),
+            '~' if !self.lox_compat => {
+                let typ = if self.check(b'/') {
+                    TokenType::TildeSlash
+                } else {
+                    TokenType::Tilde
+                };
+                Ok(self.make_token(typ))
+            }
             '!' => {
-                let typ = if self.check('=') {
+                let typ = if self.check(b'=') {
                     TokenType::BangEqual
                 } else {
                     TokenType::Bang
@@ -60,15 +226,19 @@ impl Scanner {
                 Ok(self.make_token(typ))
             }
             '=' => {
-                let typ = if self.check('=') {
+                let typ = if self.check(b'=') {
                     TokenType::EqualEqual
+                } else if !self.lox_compat && self.check(b'>') {
+                    TokenType::Arrow
                 } else {
                     TokenType::Equal
                 };
                 Ok(self.make_token(typ))
             }
             '<' => {
-                let typ = if self.check('=') {
+                let typ = if !self.lox_compat && self.check(b'<') {
+                    TokenType::LessLess
+                } else if self.check(b'=') {
                     TokenType::LessEqual
                 } else {
                     TokenType::Less
@@ -76,71 +246,285 @@ impl Scanner {
                 Ok(self.make_token(typ))
             }
             '>' => {
-                let typ = if self.check('=') {
+                let typ = if !self.lox_compat && self.check(b'>') {
+                    TokenType::GreaterGreater
+                } else if self.check(b'=') {
                     TokenType::GreaterEqual
                 } else {
                     TokenType::Greater
                 };
                 Ok(self.make_token(typ))
             }
-            '"' => Ok(self.string_token()),
+            '"' => self.string_token(false),
             _ => Err(self.error_token("Unexpected character.")),
         }
     }
 
-    pub fn lexeme(&self, start: usize, length: usize) -> String {
-        self.lexeme_at(start, length).iter().collect::<String>()
+    // Scans the token after the current one without consuming it.
+    pub fn peek_token(&mut self) -> Result<Token, Token> {
+        let saved = self.clone();
+        let token = self.scan_token();
+        *self = saved;
+        token
     }
 
-    pub fn lexeme_at(&self, start: usize, length: usize) -> &[char] {
+    // Records in `arrows` whether the parenthesized list opened by the '(' at
+    // `open`, whose first token is `first`, is followed by '=>' and so is the
+    // parameter list of an arrow function. Lists nested in it are recorded on the
+    // same pass, so that each '(' is only scanned past once. A list still open
+    // at the end of the statement isn't one, though a ';' in the body of a
+    // function inside it, like a default value's, doesn't end the statement.
+    pub fn find_arrow_parameters(
+        &self,
+        open: usize,
+        first: &Token,
+        arrows: &mut HashMap<usize, bool>,
+    ) {
+        if self.lox_compat {
+            arrows.insert(open, false);
+            return;
+        }
+
+        let mut scanner = self.clone();
+        let mut opens = vec![open];
+        let mut braces = 0;
+        let mut token = first.clone();
+        loop {
+            let next = match token.typ {
+                TokenType::LeftParen => {
+                    opens.push(token.start);
+                    None
+                }
+                TokenType::LeftBrace => {
+                    braces += 1;
+                    None
+                }
+                TokenType::RightBrace if braces > 0 => {
+                    braces -= 1;
+                    None
+                }
+                TokenType::RightParen => {
+                    let next = scanner.scan_token();
+                    let arrow = matches!(&next, Ok(token) if token.typ == TokenType::Arrow);
+                    arrows.insert(opens.pop().unwrap_or(open), arrow);
+                    if opens.is_empty() {
+                        return;
+                    }
+                    Some(next)
+                }
+                TokenType::SemiColon | TokenType::RightBrace if braces == 0 => break,
+                TokenType::Eof => break,
+                _ => None,
+            };
+
+            token = match next.unwrap_or_else(|| scanner.scan_token()) {
+                Ok(token) => token,
+                Err(_) => break,
+            };
+        }
+
+        for open in opens {
+            arrows.insert(open, false);
+        }
+    }
+
+    // Whether the tokens after a '{' that starts a statement open a map literal
+    // rather than a block: a string or number key followed by ':', or a name
+    // followed by ':' that doesn't label a loop.
+    pub fn is_map_literal(&self) -> bool {
+        if self.lox_compat {
+            return false;
+        }
+
+        let mut scanner = self.clone();
+        match (scanner.next_type(), scanner.next_type()) {
+            (TokenType::Str | TokenType::Number, TokenType::Colon) => true,
+            (TokenType::Identifier, TokenType::Colon) => {
+                !matches!(scanner.next_type(), TokenType::While | TokenType::For)
+            }
+            _ => false,
+        }
+    }
+
+    // Whether the `try` statement whose block has just been opened ends with a
+    // `finally` clause, after its block and any `catch` clause.
+    pub fn try_has_finally(&self) -> bool {
+        let mut scanner = self.clone();
+        let mut typ = scanner.skip_block();
+        if typ == TokenType::Catch {
+            while !matches!(typ, TokenType::LeftBrace | TokenType::Eof) {
+                typ = scanner.next_type();
+            }
+            typ = scanner.skip_block();
+        }
+
+        typ == TokenType::Finally
+    }
+
+    // Skips past the '}' closing the block just opened and returns the type of
+    // the token after it.
+    fn skip_block(&mut self) -> TokenType {
+        let mut depth = 1;
+        while depth > 0 {
+            match self.next_type() {
+                TokenType::LeftBrace => depth += 1,
+                TokenType::RightBrace => depth -= 1,
+                TokenType::Eof => return TokenType::Eof,
+                _ => (),
+            }
+        }
+
+        self.next_type()
+    }
+
+    fn next_type(&mut self) -> TokenType {
+        self.scan_token()
+            .map_or(TokenType::Error, |token| token.typ)
+    }
+
+    pub fn lexeme(&self, start: usize, length: usize) -> &str {
         &self.source[start..(start + length)]
     }
 
-    fn skip_whitespace_and_comments(&mut self) {
+    fn skip_whitespace_and_comments(&mut self) -> Result<(), Token> {
         while !self.is_at_end() {
             match self.peek() {
-                '\t' | ' ' | '\r' => {
+                b'\t' | b' ' | b'\r' => {
                     self.advance();
                 }
-                '\n' => {
+                b'\n' => {
                     self.advance();
                     self.line += 1;
                 }
-                '/' => {
-                    if self.peek_next() != '/' {
-                        return;
-                    }
-                    self.skip_comments();
-                }
-                _ => return,
+                b'/' => match self.peek_next() {
+                    b'/' => self.line_comment(),
+                    b'*' if !self.lox_compat => self.block_comment()?,
+                    _ => return Ok(()),
+                },
+                _ => return Ok(()),
             }
         }
+
+        Ok(())
     }
 
-    fn skip_comments(&mut self) {
-        while self.check_comment() {
-            while self.peek() != '\n' && !self.is_at_end() {
+    // Skips a `//` comment. A `///` doc comment is kept for the next token, so the
+    // compiler can attach it to the declaration that follows.
+    fn line_comment(&mut self) {
+        self.current += 2;
+        let doc = !self.lox_compat && self.peek_is(b'/') && self.peek_next() != b'/';
+        if doc {
+            self.advance();
+            if self.peek_is(b' ') {
                 self.advance();
             }
         }
-    }
 
-    fn identifier_token(&mut self) -> Token {
-        while !self.is_at_end() && self.peek().is_alphanumeric() {
+        let start = self.current;
+        while !self.is_at_end() && self.peek() != b'\n' {
             self.advance();
         }
-        let typ = self.identifier_type();
+
+        if doc {
+            let line = self
+                .lexeme(start, self.current - start)
+                .trim_end()
+                .to_string();
+            match self.pending_doc.as_mut() {
+                Some(text) => {
+                    text.push('\n');
+                    text.push_str(&line);
+                }
+                None => self.pending_doc = Some(line),
+            }
+        }
+    }
+
+    // Skips a `/* */` comment, which may contain other block comments.
+    fn block_comment(&mut self) -> Result<(), Token> {
+        let start = self.current;
+        let line = self.line;
+        self.current += 2;
+
+        let mut depth = 1;
+        while depth > 0 {
+            if self.is_at_end() {
+                return Err(Token::new(
+                    TokenType::Error,
+                    start,
+                    2,
+                    line,
+                    String::from("Unterminated block comment."),
+                ));
+            }
+
+            match self.advance() {
+                b'/' if self.peek_is(b'*') => {
+                    self.advance();
+                    depth += 1;
+                }
+                b'*' if self.peek_is(b'/') => {
+                    self.advance();
+                    depth -= 1;
+                }
+                b'\n' => self.line += 1,
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn identifier_token(&mut self) -> Token {
+        self.skip_word();
+        let typ = match self.identifier_type() {
+            typ if self.lox_compat && typ.is_extension() => TokenType::Identifier,
+            typ => typ,
+        };
         self.make_token(typ)
     }
 
-    fn number_token(&mut self) -> Token {
-        while !self.is_at_end() && self.peek().is_digit(10) {
+    // Scans a number literal after its first digit. Besides decimals, blox accepts
+    // `0x`, `0b` and `0o` prefixes, exponents and `_` between digits. The literal
+    // of the token is the number written as a plain decimal.
+    fn number_token(&mut self) -> Result<Token, Token> {
+        if self.lox_compat {
+            return Ok(self.lox_number_token());
+        }
+
+        let radix = match self.peek_is_any(b"xXbBoO") {
+            Some(prefix) if self.byte_at(self.start) == b'0' => Some(prefix),
+            _ => None,
+        };
+
+        let literal = match radix {
+            Some(prefix) => {
+                self.advance();
+                self.radix_digits(prefix)?
+            }
+            None => self.decimal_digits()?,
+        };
+
+        if !self.is_at_end() && self.is_identifier_continue(self.peek_char()) {
+            self.skip_word();
+            let lexeme = self.lexeme(self.start, self.current - self.start);
+            let message = format!("Invalid number literal '{}'.", lexeme);
+            return Err(self.error_token(&message));
+        }
+
+        let mut token = self.make_token(TokenType::Number);
+        token.literal = Some(literal);
+        Ok(token)
+    }
+
+    fn lox_number_token(&mut self) -> Token {
+        while !self.is_at_end() && self.peek().is_ascii_digit() {
             self.advance();
         }
 
-        if !self.is_at_end() && self.peek() == '.' && self.peek_next().is_digit(10) {
+        if self.peek_is(b'.') && self.peek_next().is_ascii_digit() {
             self.advance(); // consume the '.'.
-            while !self.is_at_end() && self.peek().is_digit(10) {
+            while !self.is_at_end() && self.peek().is_ascii_digit() {
                 self.advance();
             }
         }
@@ -148,22 +532,325 @@ impl Scanner {
         self.make_token(TokenType::Number)
     }
 
-    fn string_token(&mut self) -> Token {
-        self.start += 1;
-        while !self.is_at_end() && self.peek() != '"' {
-            if self.peek() == '\n' {
-                self.line += 1;
+    fn decimal_digits(&mut self) -> Result<String, Token> {
+        let mut literal = String::from(self.byte_at(self.start) as char);
+        self.digit_run(&mut literal)?;
+
+        if self.peek_is(b'.') && self.peek_next().is_ascii_digit() {
+            literal.push(self.advance() as char);
+            literal.push(self.advance() as char);
+            self.digit_run(&mut literal)?;
+        }
+
+        if self.peek_is_any(b"eE").is_some() {
+            literal.push(self.advance() as char);
+            if let Some(sign) = self.peek_is_any(b"+-") {
+                self.advance();
+                literal.push(sign as char);
+            }
+
+            if self.is_at_end() || !self.peek().is_ascii_digit() {
+                return Err(self.error_token("Expect digits in exponent."));
+            }
+            literal.push(self.advance() as char);
+            self.digit_run(&mut literal)?;
+        }
+
+        Ok(literal)
+    }
+
+    // Consumes the digits and separators following a digit that has already been
+    // pushed onto the literal.
+    fn digit_run(&mut self, literal: &mut String) -> Result<(), Token> {
+        while !self.is_at_end() && (self.peek().is_ascii_digit() || self.peek() == b'_') {
+            let c = self.advance();
+            if c != b'_' {
+                literal.push(c as char);
+            } else if self.is_at_end() || !self.peek().is_ascii_digit() {
+                return Err(self.separator_error());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn radix_digits(&mut self, prefix: u8) -> Result<String, Token> {
+        let (radix, name) = match prefix.to_ascii_lowercase() {
+            b'x' => (16, "hexadecimal"),
+            b'b' => (2, "binary"),
+            _ => (8, "octal"),
+        };
+
+        let mut value = Some(0_i64);
+        let mut digits = 0;
+        let mut previous = prefix as char;
+        while !self.is_at_end() && self.is_identifier_continue(self.peek_char()) {
+            let c = self.peek_char();
+            self.current += c.len_utf8();
+            if c == '_' {
+                if !previous.is_digit(radix) {
+                    return Err(self.separator_error());
+                }
+            } else if let Some(digit) = c.to_digit(radix) {
+                value = value
+                    .and_then(|value| value.checked_mul(radix as i64))
+                    .and_then(|value| value.checked_add(digit as i64));
+                digits += 1;
+            } else {
+                self.skip_word();
+                let message = format!("Invalid digit '{}' in {} literal.", c, name);
+                return Err(self.error_token(&message));
+            }
+            previous = c;
+        }
+
+        if digits == 0 {
+            let message = format!("Expect digits after '0{}'.", prefix as char);
+            return Err(self.error_token(&message));
+        }
+
+        if previous == '_' {
+            return Err(self.separator_error());
+        }
+
+        match value {
+            Some(value) => Ok(value.to_string()),
+            None => Err(self.error_token("Integer literal is too large.")),
+        }
+    }
+
+    fn separator_error(&mut self) -> Token {
+        self.skip_word();
+        self.error_token("Digit separator '_' must be between digits.")
+    }
+
+    // Skips the rest of a malformed literal so it is reported as a single error.
+    fn skip_word(&mut self) {
+        while !self.is_at_end() {
+            let c = self.peek_char();
+            if !self.is_identifier_continue(c) {
+                return;
+            }
+            self.current += c.len_utf8();
+        }
+    }
+
+    // Identifiers start with a letter or '_' and continue with letters, digits and
+    // '_', where letters are anything Unicode allows in identifiers (XID_Start and
+    // XID_Continue). Reference Lox only has ASCII identifiers.
+    fn is_identifier_start(&self, c: char) -> bool {
+        if self.lox_compat {
+            return c.is_ascii_alphabetic() || c == '_';
+        }
+
+        c == '_' || c.is_xid_start()
+    }
+
+    fn is_identifier_continue(&self, c: char) -> bool {
+        if self.lox_compat {
+            return c.is_ascii_alphanumeric() || c == '_';
+        }
+
+        c.is_xid_continue()
+    }
+
+    // Scans a string literal after its opening quote. Raw strings keep
+    // backslashes as they are, and triple-quoted strings may span lines with their
+    // common indentation stripped. Other strings may embed `${expr}`, which ends
+    // the literal with an interpolation segment. In raw and triple-quoted strings
+    // `${` is plain text, since their indentation is stripped from the literal as
+    // a whole.
+    fn string_token(&mut self, raw: bool) -> Result<Token, Token> {
+        let triple = !self.lox_compat && self.peek_is(b'"') && self.peek_next() == b'"';
+        if triple {
+            self.current += 2;
+        }
+
+        self.string_body(raw, triple)
+    }
+
+    fn string_body(&mut self, raw: bool, triple: bool) -> Result<Token, Token> {
+        let interpolate = !raw && !triple && !self.lox_compat;
+        let content_start = self.current;
+        let mut has_escapes = false;
+        let mut error = None;
+        let mut typ = TokenType::Str;
+        loop {
+            if self.is_at_end() {
+                return Err(self
+                    .unterminated_interpolation()
+                    .unwrap_or_else(|| self.error_token("Unterminated string.")));
+            }
+
+            match self.peek() {
+                b'"' if !triple || self.peek_is_closing_triple_quote() => break,
+                b'\\' if !raw && !self.lox_compat => {
+                    has_escapes = true;
+                    if let Err(token) = self.escape_sequence() {
+                        error.get_or_insert(token);
+                    }
+                    continue;
+                }
+                b'$' if interpolate && self.peek_next() == b'{' => {
+                    if let Err(token) = self.empty_interpolation() {
+                        error.get_or_insert(token);
+                        continue;
+                    }
+
+                    typ = TokenType::Interpolation;
+                    break;
+                }
+                b'\n' => self.line += 1,
+                _ => (),
             }
             self.advance();
         }
 
-        if self.is_at_end() {
-            return self.error_token("Unterminated string.");
+        let content_end = self.current;
+        if typ == TokenType::Interpolation {
+            self.interpolations.push(OpenInterpolation {
+                start: self.current,
+                line: self.line,
+                depth: 0,
+                conditionals: 0,
+            });
+            self.current += 2;
+            self.pending_error = error;
+        } else {
+            self.current += if triple { 3 } else { 1 };
+            if let Some(token) = error {
+                return Err(token);
+            }
+        }
+
+        let mut token = Token::new(
+            typ,
+            content_start,
+            content_end - content_start,
+            self.line,
+            String::new(),
+        );
+        if triple || has_escapes {
+            let content = self.lexeme(content_start, content_end - content_start);
+            let content = if triple {
+                trim_indent(content)
+            } else {
+                content.to_string()
+            };
+            token.literal = Some(if has_escapes {
+                decode_escapes(&content)
+            } else {
+                content
+            });
+        }
+
+        Ok(token)
+    }
+
+    // Skips over a `${}` with nothing inside, which is reported as an error.
+    fn empty_interpolation(&mut self) -> Result<(), Token> {
+        let start = self.current;
+        let mut end = start + 2;
+        while end < self.source.len() && matches!(self.byte_at(end), b' ' | b'\t') {
+            end += 1;
+        }
+
+        if end >= self.source.len() || self.byte_at(end) != b'}' {
+            return Ok(());
+        }
+
+        self.current = end + 1;
+        Err(Token::new(
+            TokenType::Error,
+            start,
+            self.current - start,
+            self.line,
+            String::from("Expect expression in interpolation."),
+        ))
+    }
+
+    fn unterminated_interpolation(&mut self) -> Option<Token> {
+        let interpolation = self.interpolations.first()?.clone();
+        self.interpolations.clear();
+        Some(Token::new(
+            TokenType::Error,
+            interpolation.start,
+            2,
+            interpolation.line,
+            String::from("Unterminated interpolation."),
+        ))
+    }
+
+    fn close_nesting(&mut self) {
+        if let Some(interpolation) = self.interpolations.last_mut() {
+            interpolation.depth = interpolation.depth.saturating_sub(1);
         }
+    }
 
-        // Closing quote
+    // Scans the format specifier after a ':' in an interpolation, up to its '}'.
+    fn format_spec_token(&mut self) -> Token {
+        self.start = self.current;
+        while !self.is_at_end() && !matches!(self.peek(), b'}' | b'"' | b'\n') {
+            self.advance();
+        }
+
+        self.make_token(TokenType::FormatSpec)
+    }
+
+    // Checks the escape sequence starting at the current backslash and moves past
+    // it, reporting an invalid one at its own position.
+    fn escape_sequence(&mut self) -> Result<(), Token> {
+        let start = self.current;
         self.advance();
-        self.make_token(TokenType::Str)
+        if self.is_at_end() {
+            return Ok(());
+        }
+
+        let escaped = self.peek_char();
+        self.current += escaped.len_utf8();
+        match escaped {
+            'n' | 't' | 'r' | '0' | '\\' | '"' | '\'' | '$' => Ok(()),
+            'u' => {
+                let digits_start = self.current + 1;
+                if !self.check(b'{') {
+                    return Err(self.escape_error(start, "Invalid unicode escape sequence"));
+                }
+
+                while !self.is_at_end() && self.peek().is_ascii_hexdigit() {
+                    self.advance();
+                }
+
+                let digits = self.lexeme(digits_start, self.current - digits_start);
+                let valid = !digits.is_empty()
+                    && digits.len() <= 6
+                    && u32::from_str_radix(digits, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .is_some();
+                if !self.check(b'}') || !valid {
+                    return Err(self.escape_error(start, "Invalid unicode escape sequence"));
+                }
+
+                Ok(())
+            }
+            '\n' => {
+                self.line += 1;
+                Err(self.escape_error(start, "Invalid escape sequence"))
+            }
+            _ => Err(self.escape_error(start, "Invalid escape sequence")),
+        }
+    }
+
+    fn escape_error(&self, start: usize, message: &str) -> Token {
+        let length = self.current - start;
+        let escape = self.lexeme(start, length);
+        Token::new(
+            TokenType::Error,
+            start,
+            length,
+            self.line,
+            format!("{} '{}'.", message, escape.trim_end()),
+        )
     }
 
     fn error_token(&self, message: &str) -> Token {
@@ -184,17 +871,21 @@ impl Scanner {
         Token::new(typ, self.start, length, self.line, String::new())
     }
 
-    fn advance(&mut self) -> char {
-        self.current += 1;
-        self.source[self.current - 1]
+    fn peek_is_any(&self, expected: &[u8]) -> Option<u8> {
+        if self.is_at_end() || !expected.contains(&self.peek()) {
+            return None;
+        }
+
+        Some(self.peek())
     }
 
-    fn check(&mut self, expected: char) -> bool {
-        if self.is_at_end() {
-            return false;
-        }
+    fn advance(&mut self) -> u8 {
+        self.current += 1;
+        self.byte_at(self.current - 1)
+    }
 
-        if self.source[self.current] != expected {
+    fn check(&mut self, expected: u8) -> bool {
+        if !self.peek_is(expected) {
             return false;
         }
 
@@ -202,32 +893,38 @@ impl Scanner {
         true
     }
 
-    fn check_comment(&mut self) -> bool {
-        if self.is_at_end() {
-            return false;
-        }
-
-        if self.peek() != '/' {
-            return false;
-        }
+    fn peek_is(&self, expected: u8) -> bool {
+        !self.is_at_end() && self.peek() == expected
+    }
 
-        if self.peek_next() != '/' {
-            return false;
-        }
+    fn peek_is_closing_triple_quote(&self) -> bool {
+        self.source.as_bytes()[self.current..].starts_with(b"\"\"\"")
+    }
 
-        self.current += 2;
-        true
+    fn peek(&self) -> u8 {
+        self.byte_at(self.current)
     }
 
-    fn peek(&self) -> char {
-        self.source[self.current]
+    fn peek_next(&self) -> u8 {
+        if self.current + 1 >= self.source.len() {
+            return b'\0';
+        }
+        self.byte_at(self.current + 1)
     }
 
-    fn peek_next(&self) -> char {
-        if self.is_at_end() {
-            return '\0';
+    // Decodes the whole character at the current position, which may take up
+    // more than one byte.
+    fn peek_char(&self) -> char {
+        let c = self.peek();
+        if c.is_ascii() {
+            return c as char;
         }
-        self.source[self.current + 1]
+
+        self.source[self.current..].chars().next().unwrap_or('\0')
+    }
+
+    fn byte_at(&self, index: usize) -> u8 {
+        self.source.as_bytes()[index]
     }
 
     fn is_at_end(&self) -> bool {
@@ -235,76 +932,100 @@ impl Scanner {
     }
 
     fn identifier_type(&self) -> TokenType {
-        match self.source[self.start] {
-            'a' => self.check_keyword("nd", TokenType::And),
-            'e' => self.check_keyword("lse", TokenType::Else),
-            'i' => self.check_keyword("f", TokenType::If),
-            'n' => self.check_keyword("il", TokenType::Nil),
-            'o' => self.check_keyword("r", TokenType::Or),
-            'p' => self.check_keyword("rint", TokenType::Print),
-            'r' => self.check_keyword("eturn", TokenType::Return),
-            'w' => self.check_keyword("hile", TokenType::While),
-            'd' => self.check_keyword("efault", TokenType::Default),
-            'c' => {
+        match self.byte_at(self.start) {
+            b'a' => self.check_keyword("nd", TokenType::And),
+            b'b' => self.check_keyword("reak", TokenType::Break),
+            b'e' => self.check_keyword("lse", TokenType::Else),
+            b'i' => match self.check_keyword("f", TokenType::If) {
+                TokenType::Identifier => match self.check_keyword("n", TokenType::In) {
+                    TokenType::Identifier => self.check_keyword("mport", TokenType::Import),
+                    typ => typ,
+                },
+                typ => typ,
+            },
+            b'n' => self.check_keyword("il", TokenType::Nil),
+            b'o' => self.check_keyword("r", TokenType::Or),
+            b'p' => self.check_keyword("rint", TokenType::Print),
+            b'r' => self.check_keyword("eturn", TokenType::Return),
+            b'w' => self.check_keyword("hile", TokenType::While),
+            b'y' => self.check_keyword("ield", TokenType::Yield),
+            b'd' => self.check_keyword("efault", TokenType::Default),
+            b'c' => {
                 if self.current - self.start == 1 {
                     return TokenType::Identifier;
                 }
 
-                match self.source[self.start + 1] {
-                    'a' => self.check_keyword("ase", TokenType::Case),
-                    'l' => self.check_keyword("lass", TokenType::Class),
+                match self.byte_at(self.start + 1) {
+                    b'a' => match self.check_keyword("ase", TokenType::Case) {
+                        TokenType::Identifier => self.check_keyword("atch", TokenType::Catch),
+                        typ => typ,
+                    },
+                    b'l' => self.check_keyword("lass", TokenType::Class),
+                    b'o' => self.check_keyword("ontinue", TokenType::Continue),
                     _ => TokenType::Identifier,
                 }
             }
-            's' => {
+            b's' => {
                 if self.current - self.start == 1 {
                     return TokenType::Identifier;
                 }
 
-                match self.source[self.start + 1] {
-                    'w' => self.check_keyword("witch", TokenType::Switch),
-                    'u' => self.check_keyword("uper", TokenType::Super),
+                match self.byte_at(self.start + 1) {
+                    b'w' => self.check_keyword("witch", TokenType::Switch),
+                    b'u' => self.check_keyword("uper", TokenType::Super),
                     _ => TokenType::Identifier,
                 }
             }
-            'v' => {
+            b'v' => {
                 if self.current - self.start == 1
-                    || self.source[self.start + 1] != 'a'
+                    || self.byte_at(self.start + 1) != b'a'
                     || self.current - self.start == 2
                 {
                     return TokenType::Identifier;
                 }
 
-                if self.source[self.start + 2] == 'r' && self.current - self.start == 3 {
+                if self.byte_at(self.start + 2) == b'r' && self.current - self.start == 3 {
                     return TokenType::Var;
                 }
 
-                if self.source[self.start + 2] == 'l' && self.current - self.start == 3 {
+                if self.byte_at(self.start + 2) == b'l' && self.current - self.start == 3 {
                     return TokenType::Val;
                 }
 
                 TokenType::Identifier
             }
-            'f' => {
+            b'f' => {
                 if self.current - self.start == 1 {
                     return TokenType::Identifier;
                 }
 
-                match self.source[self.start + 1] {
-                    'a' => self.check_keyword("alse", TokenType::False),
-                    'o' => self.check_keyword("or", TokenType::For),
-                    'u' => self.check_keyword("un", TokenType::Fun),
+                match self.byte_at(self.start + 1) {
+                    b'a' => match self.check_keyword("alse", TokenType::False) {
+                        TokenType::Identifier => {
+                            self.check_keyword("allthrough", TokenType::Fallthrough)
+                        }
+                        typ => typ,
+                    },
+                    b'i' => self.check_keyword("inally", TokenType::Finally),
+                    b'o' => self.check_keyword("or", TokenType::For),
+                    b'u' => self.check_keyword("un", TokenType::Fun),
                     _ => TokenType::Identifier,
                 }
             }
-            't' => {
+            b't' => {
                 if self.current - self.start == 1 {
                     return TokenType::Identifier;
                 }
 
-                match self.source[self.start + 1] {
-                    'h' => self.check_keyword("his", TokenType::This),
-                    'r' => self.check_keyword("rue", TokenType::True),
+                match self.byte_at(self.start + 1) {
+                    b'h' => match self.check_keyword("his", TokenType::This) {
+                        TokenType::Identifier => self.check_keyword("hrow", TokenType::Throw),
+                        typ => typ,
+                    },
+                    b'r' => match self.check_keyword("rue", TokenType::True) {
+                        TokenType::Identifier => self.check_keyword("ry", TokenType::Try),
+                        typ => typ,
+                    },
                     _ => TokenType::Identifier,
                 }
             }
@@ -313,14 +1034,67 @@ impl Scanner {
     }
 
     fn check_keyword(&self, rest: &str, typ: TokenType) -> TokenType {
-        if self.current - self.start == rest.len() + 1 {
-            let source_value = &self.source[self.start + 1..self.current];
-            let rest_value: Vec<char> = rest.chars().collect();
-            if source_value == rest_value {
-                return typ;
-            }
+        if &self.source.as_bytes()[self.start + 1..self.current] == rest.as_bytes() {
+            return typ;
         }
 
         TokenType::Identifier
     }
 }
+
+// Replaces the escape sequences in a string the scanner has already validated.
+fn decode_escapes(content: &str) -> String {
+    let mut decoded = String::with_capacity(content.len());
+    let mut chars = content.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            decoded.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => decoded.push('\n'),
+            Some('t') => decoded.push('\t'),
+            Some('r') => decoded.push('\r'),
+            Some('0') => decoded.push('\0'),
+            Some('u') => {
+                let digits: String = chars.by_ref().skip(1).take_while(|c| *c != '}').collect();
+                if let Some(c) = u32::from_str_radix(&digits, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                {
+                    decoded.push(c);
+                }
+            }
+            Some(c) => decoded.push(c),
+            None => (),
+        }
+    }
+
+    decoded
+}
+
+// Drops the blank first and last lines of a multi-line string and removes the
+// indentation shared by the remaining non-blank lines.
+fn trim_indent(content: &str) -> String {
+    let mut lines: Vec<&str> = content.split('\n').collect();
+    if lines.len() > 1 && lines[0].trim().is_empty() {
+        lines.remove(0);
+    }
+    if lines.len() > 1 && lines[lines.len() - 1].trim().is_empty() {
+        lines.pop();
+    }
+
+    let indent = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start_matches([' ', '\t']).len())
+        .min()
+        .unwrap_or(0);
+
+    lines
+        .iter()
+        .map(|line| line.get(indent..).unwrap_or("").trim_end_matches('\r'))
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
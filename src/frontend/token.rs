@@ -3,10 +3,15 @@ use super::token_type::TokenType;
 #[derive(Debug, Clone)]
 pub struct Token {
     pub typ: TokenType,
+    // Byte offset and length of the lexeme in the source.
     pub start: usize,
     pub length: usize,
     pub line: usize,
     pub message: String,
+    // Decoded contents of a string literal whose source text can't be used as is.
+    pub literal: Option<String>,
+    // Text of the `///` doc comment written right before the token.
+    pub doc: Option<String>,
 }
 
 impl Token {
@@ -17,6 +22,8 @@ impl Token {
             length,
             line,
             message,
+            literal: None,
+            doc: None,
         }
     }
 
@@ -9,14 +9,25 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
     Dot,
+    DotDot,
+    DotDotEqual,
+    DotDotDot,
     Minus,
     Plus,
     SemiColon,
     Colon,
     Slash,
     Star,
+    Percent,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
+    Question,
     // One or two character tokens
     Bang,
     BangEqual,
@@ -26,13 +37,31 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    LessLess,
+    GreaterGreater,
+    StarStar,
+    TildeSlash,
+    PlusEqual,
+    MinusEqual,
+    StarEqual,
+    SlashEqual,
+    PercentEqual,
+    PlusPlus,
+    MinusMinus,
+    QuestionQuestion,
+    QuestionDot,
+    Arrow,
     // Literals
     Identifier,
     Str,
+    Interpolation,
+    FormatSpec,
     Number,
     // Keywords
     And,
+    Break,
     Class,
+    Continue,
     Else,
     False,
     Fun,
@@ -50,6 +79,14 @@ pub enum TokenType {
     Switch,
     Case,
     Default,
+    Fallthrough,
+    In,
+    Try,
+    Catch,
+    Finally,
+    Throw,
+    Yield,
+    Import,
     While,
     Error,
     Eof,
@@ -57,16 +94,65 @@ pub enum TokenType {
 }
 
 impl TokenType {
+    // Keywords blox adds on top of reference Lox.
+    pub fn is_extension(&self) -> bool {
+        matches!(
+            self,
+            Self::Val
+                | Self::Switch
+                | Self::Case
+                | Self::Default
+                | Self::Fallthrough
+                | Self::In
+                | Self::Try
+                | Self::Catch
+                | Self::Finally
+                | Self::Throw
+                | Self::Yield
+                | Self::Import
+                | Self::Break
+                | Self::Continue
+        )
+    }
+
+    // Operators that store a new value in the variable before them.
+    pub fn is_assignment(&self) -> bool {
+        matches!(
+            self,
+            Self::Equal
+                | Self::PlusEqual
+                | Self::MinusEqual
+                | Self::StarEqual
+                | Self::SlashEqual
+                | Self::PercentEqual
+                | Self::PlusPlus
+                | Self::MinusMinus
+        )
+    }
+
+    // Declarations a `///` doc comment can be attached to.
+    pub fn is_documentable(&self) -> bool {
+        matches!(self, Self::Fun | Self::Val | Self::Var)
+    }
+
     pub fn precedence(&self) -> Precedence {
         match self {
-            Self::Slash | Self::Star => Precedence::Factor,
+            Self::Slash | Self::Star | Self::Percent | Self::TildeSlash => Precedence::Factor,
+            Self::StarStar => Precedence::Exponent,
+            Self::LessLess | Self::GreaterGreater => Precedence::Shift,
+            Self::Ampersand => Precedence::BitAnd,
+            Self::Caret => Precedence::BitXor,
+            Self::Pipe => Precedence::BitOr,
             Self::Minus | Self::Plus => Precedence::Term,
             Self::BangEqual | Self::EqualEqual => Precedence::Equality,
             Self::Greater | Self::GreaterEqual | Self::Less | Self::LessEqual => {
                 Precedence::Comparison
             }
+            Self::Question => Precedence::Conditional,
+            Self::QuestionQuestion => Precedence::Coalesce,
             Self::And => Precedence::And,
             Self::Or => Precedence::Or,
+            Self::LeftParen | Self::LeftBracket | Self::Dot | Self::QuestionDot => Precedence::Call,
             _ => Precedence::None,
         }
     }
@@ -79,14 +165,25 @@ impl fmt::Display for TokenType {
             Self::RightParen => write!(f, "RIGHT_PAREN"),
             Self::LeftBrace => write!(f, "LEFT_BRACE"),
             Self::RightBrace => write!(f, "RIGHT_BRACE"),
+            Self::LeftBracket => write!(f, "LEFT_BRACKET"),
+            Self::RightBracket => write!(f, "RIGHT_BRACKET"),
             Self::Comma => write!(f, "COMMA"),
             Self::Dot => write!(f, "DOT"),
+            Self::DotDot => write!(f, "DOT_DOT"),
+            Self::DotDotEqual => write!(f, "DOT_DOT_EQUAL"),
+            Self::DotDotDot => write!(f, "DOT_DOT_DOT"),
             Self::Minus => write!(f, "MINUS"),
             Self::Plus => write!(f, "PLUS"),
             Self::SemiColon => write!(f, "SEMI_COLON"),
             Self::Colon => write!(f, "COLON"),
             Self::Slash => write!(f, "SLASH"),
             Self::Star => write!(f, "STAR"),
+            Self::Percent => write!(f, "PERCENT"),
+            Self::Ampersand => write!(f, "AMPERSAND"),
+            Self::Pipe => write!(f, "PIPE"),
+            Self::Caret => write!(f, "CARET"),
+            Self::Tilde => write!(f, "TILDE"),
+            Self::Question => write!(f, "QUESTION"),
             Self::Bang => write!(f, "BANG"),
             Self::BangEqual => write!(f, "BANG_EQUAL"),
             Self::Equal => write!(f, "EQUAL"),
@@ -95,11 +192,29 @@ impl fmt::Display for TokenType {
             Self::GreaterEqual => write!(f, "GREATER_EQUAL"),
             Self::Less => write!(f, "LESS"),
             Self::LessEqual => write!(f, "LESS_EQUAL"),
+            Self::LessLess => write!(f, "LESS_LESS"),
+            Self::GreaterGreater => write!(f, "GREATER_GREATER"),
+            Self::StarStar => write!(f, "STAR_STAR"),
+            Self::TildeSlash => write!(f, "TILDE_SLASH"),
+            Self::PlusEqual => write!(f, "PLUS_EQUAL"),
+            Self::MinusEqual => write!(f, "MINUS_EQUAL"),
+            Self::StarEqual => write!(f, "STAR_EQUAL"),
+            Self::SlashEqual => write!(f, "SLASH_EQUAL"),
+            Self::PercentEqual => write!(f, "PERCENT_EQUAL"),
+            Self::PlusPlus => write!(f, "PLUS_PLUS"),
+            Self::MinusMinus => write!(f, "MINUS_MINUS"),
+            Self::QuestionQuestion => write!(f, "QUESTION_QUESTION"),
+            Self::QuestionDot => write!(f, "QUESTION_DOT"),
+            Self::Arrow => write!(f, "ARROW"),
             Self::Identifier => write!(f, "IDENTIFIER"),
             Self::Str => write!(f, "STR"),
+            Self::Interpolation => write!(f, "INTERPOLATION"),
+            Self::FormatSpec => write!(f, "FORMAT_SPEC"),
             Self::Number => write!(f, "NUMBER"),
             Self::And => write!(f, "AND"),
+            Self::Break => write!(f, "BREAK"),
             Self::Class => write!(f, "CLASS"),
+            Self::Continue => write!(f, "CONTINUE"),
             Self::Else => write!(f, "ELSE"),
             Self::False => write!(f, "FALSE"),
             Self::Fun => write!(f, "FUN"),
@@ -117,6 +232,14 @@ impl fmt::Display for TokenType {
             Self::Switch => write!(f, "SWITCH"),
             Self::Case => write!(f, "CASE"),
             Self::Default => write!(f, "DEFAULT"),
+            Self::Fallthrough => write!(f, "FALLTHROUGH"),
+            Self::In => write!(f, "IN"),
+            Self::Try => write!(f, "TRY"),
+            Self::Catch => write!(f, "CATCH"),
+            Self::Finally => write!(f, "FINALLY"),
+            Self::Throw => write!(f, "THROW"),
+            Self::Yield => write!(f, "YIELD"),
+            Self::Import => write!(f, "IMPORT"),
             Self::While => write!(f, "WHILE"),
             Self::Error => write!(f, "ERROR"),
             Self::Eof => write!(f, "EOF"),
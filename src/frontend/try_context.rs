@@ -0,0 +1,61 @@
+// A `try` statement being compiled. When it has a `finally` clause, two hidden
+// locals starting at `slot` hold how control reached the clause and the value
+// that goes with it: a thrown value, or the value being returned.
+pub struct TryContext {
+    pub scope_depth: usize,
+    pub slot: usize,
+    pub has_finally: bool,
+    pub region: TryRegion,
+    // Jumps into the `finally` clause, to patch once it is reached.
+    pub entries: Vec<usize>,
+    // The returns, breaks and continues that leave through the `finally`
+    // clause, each of which resumes once the clause has run.
+    pub exits: Vec<TryExit>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum TryRegion {
+    Body,
+    Catch,
+    Finally,
+}
+
+#[derive(Clone, Copy)]
+pub enum TryExit {
+    Return,
+    Break(usize),
+    Continue(usize),
+}
+
+// What the second hidden local holds: normal completion, a value thrown past
+// the clauses, or `EXIT` plus the index of one of `exits`.
+pub const COMPLETED: f64 = 0.0;
+pub const THROWN: f64 = 1.0;
+pub const EXIT: f64 = 2.0;
+
+impl TryContext {
+    pub fn new(scope_depth: usize, slot: usize, has_finally: bool) -> TryContext {
+        TryContext {
+            scope_depth,
+            slot,
+            has_finally,
+            region: TryRegion::Body,
+            entries: vec![],
+            exits: vec![],
+        }
+    }
+
+    // How many handlers the statement has running in its current region.
+    pub fn handlers(&self) -> usize {
+        match self.region {
+            TryRegion::Body => 1,
+            TryRegion::Catch => self.has_finally as usize,
+            TryRegion::Finally => 0,
+        }
+    }
+
+    // Whether leaving from the current region has to run the `finally` clause.
+    pub fn runs_finally(&self) -> bool {
+        self.has_finally && self.region != TryRegion::Finally
+    }
+}
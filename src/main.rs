@@ -4,41 +4,65 @@ mod frontend;
 
 use std::{env, process};
 
-use crate::{backend::vm::Vm, error::codes::ErrCode};
+use crate::{
+    backend::{arithmetic::Overflow, vm::Vm},
+    error::codes::ErrCode,
+};
 
 const DEBUG_PRINT_ARG: &str = "-p";
 const DEBUG_TRACE_ARG: &str = "-t";
+const LOX_COMPAT_ARG: &str = "-l";
+const OVERFLOW_ARG: &str = "-o";
+const PRINT_DOCS_ARG: &str = "-d";
+const SEARCH_PATH_ARG: &str = "-I";
+const USAGE: &str = "Usage: blox [path] -p? -t? -l? -d? (-I dir)* (-o error|wrap|promote)?";
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut path = None;
+    let mut debug_print_code = false;
+    let mut debug_trace = false;
+    let mut lox_compat = false;
+    let mut print_docs = false;
+    let mut search_path = vec![];
+    let mut overflow = Overflow::default();
+    let mut usage_error = None;
 
-    let mut vm = Vm::new();
-
-    let result = match args.len() {
-        1 => vm.repl(),
-        2 => vm.run_file(&args[1], false, false),
-        3 => {
-            if args[2] == DEBUG_PRINT_ARG {
-                vm.run_file(&args[1], true, false)
-            } else if args[2] == DEBUG_TRACE_ARG {
-                vm.run_file(&args[1], false, true)
-            } else {
-                Err(ErrCode::Io(format!("Unrecognized arg {}", args[2])))
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            DEBUG_PRINT_ARG => debug_print_code = true,
+            DEBUG_TRACE_ARG => debug_trace = true,
+            LOX_COMPAT_ARG => lox_compat = true,
+            PRINT_DOCS_ARG => print_docs = true,
+            SEARCH_PATH_ARG => match args.next() {
+                Some(dir) => search_path.push(dir),
+                None => usage_error = Some(ErrCode::Io(String::from(USAGE))),
+            },
+            OVERFLOW_ARG => match args.next().map(|mode| mode.parse()) {
+                Some(Ok(mode)) => overflow = mode,
+                Some(Err(e)) => usage_error = Some(ErrCode::Io(e)),
+                None => usage_error = Some(ErrCode::Io(String::from(USAGE))),
+            },
+            _ if arg.starts_with('-') => {
+                usage_error = Some(ErrCode::Io(format!("Unrecognized arg {}", arg)))
             }
+            _ if path.is_none() => path = Some(arg),
+            _ => usage_error = Some(ErrCode::Io(String::from(USAGE))),
         }
-        4 => {
-            if (args[2] == DEBUG_PRINT_ARG || args[3] == DEBUG_PRINT_ARG)
-                && (args[2] == DEBUG_TRACE_ARG || args[3] == DEBUG_TRACE_ARG)
-            {
-                vm.run_file(&args[1], true, true)
-            } else {
-                Err(ErrCode::Io(format!(
-                    "Unrecognized arg {} {}",
-                    args[3], args[4]
-                )))
-            }
-        }
-        _ => Err(ErrCode::Io(String::from("Usage: blox [path] -p? -t?"))),
+    }
+
+    let mut vm = Vm::new();
+    vm.set_lox_compat(lox_compat);
+    vm.set_print_docs(print_docs);
+    vm.set_overflow(overflow);
+    for dir in search_path.iter() {
+        vm.add_search_path(dir);
+    }
+
+    let result = match (usage_error, path) {
+        (Some(e), _) => Err(e),
+        (None, Some(path)) => vm.run_file(&path, debug_print_code, debug_trace),
+        (None, None) => vm.repl(),
     };
 
     match result {
@@ -0,0 +1,287 @@
+//! Golden-file conformance tests.
+//!
+//! Every `.lox` file under `tests/lox` is run through the `blox` binary and its
+//! output is compared against the annotations in the file, using the same format
+//! as the Crafting Interpreters test suite:
+//!
+//! - `// expect: value` expects `value` as the next line of standard output.
+//! - `// expect runtime error: msg` expects the script to fail at runtime with `msg`
+//!   reported on this line.
+//! - `// Error at 'x': msg` expects a compile error reported on this line. An
+//!   explicit line can be given with `// [line 3] Error at 'x': msg`, which is
+//!   also how errors in an imported module are expected before the runtime error
+//!   importing it fails with.
+//! - `// Warning at 'x': msg` expects a compile warning reported on this line.
+//!
+//! Files under a directory listed in `DIRECTORY_FLAGS` are run with the extra
+//! command line flags given for it. Files under a `lib` directory are modules
+//! imported by the tests and aren't run on their own.
+
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+const EXPECT: &str = "// expect: ";
+const EXPECT_RUNTIME_ERROR: &str = "// expect runtime error: ";
+const COMPILE_ERROR_EXIT: i32 = 65;
+const RUNTIME_ERROR_EXIT: i32 = 70;
+const MODULE_DIRECTORY: &str = "lib";
+const DIRECTORY_FLAGS: &[(&str, &[&str])] = &[
+    ("lox_compat", &["-l"]),
+    ("docs", &["-d"]),
+    ("disassembly", &["-p"]),
+    ("search_path", &["-I", "tests/lox/modules/search_path/lib"]),
+    ("overflow_wrap", &["-o", "wrap"]),
+    ("overflow_promote", &["-o", "promote"]),
+];
+
+struct Expectations {
+    output: Vec<String>,
+    compile_errors: Vec<String>,
+    warnings: Vec<String>,
+    runtime_error: Option<(String, usize)>,
+}
+
+impl Expectations {
+    fn parse(source: &str) -> Self {
+        let mut expectations = Self {
+            output: vec![],
+            compile_errors: vec![],
+            warnings: vec![],
+            runtime_error: None,
+        };
+
+        for (index, line) in source.lines().enumerate() {
+            let line_number = index + 1;
+            if let Some(output) = annotation(line, EXPECT) {
+                expectations.output.push(output.to_string());
+            } else if let Some(message) = annotation(line, EXPECT_RUNTIME_ERROR) {
+                expectations.runtime_error = Some((message.to_string(), line_number));
+            } else if let Some(error) = diagnostic(line, line_number, "Error") {
+                expectations.compile_errors.push(error);
+            } else if let Some(warning) = diagnostic(line, line_number, "Warning") {
+                expectations.warnings.push(warning);
+            }
+        }
+
+        expectations
+    }
+
+    fn exit_code(&self) -> i32 {
+        if self.runtime_error.is_some() {
+            RUNTIME_ERROR_EXIT
+        } else if !self.compile_errors.is_empty() {
+            COMPILE_ERROR_EXIT
+        } else {
+            0
+        }
+    }
+}
+
+fn annotation<'a>(line: &'a str, prefix: &str) -> Option<&'a str> {
+    line.find(prefix).map(|index| &line[index + prefix.len()..])
+}
+
+fn diagnostic(line: &str, line_number: usize, kind: &str) -> Option<String> {
+    let index = line.find("// ")?;
+    let comment = &line[index + 3..];
+    if comment.starts_with(kind) {
+        return Some(format!("[line {}] {}", line_number, comment));
+    }
+
+    if comment.starts_with("[line ") && comment.contains(&format!("] {}", kind)) {
+        return Some(comment.to_string());
+    }
+
+    None
+}
+
+struct Failure {
+    path: PathBuf,
+    messages: Vec<String>,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "FAIL {}", self.path.display())?;
+        for message in self.messages.iter() {
+            writeln!(f, "     {}", message)?;
+        }
+        Ok(())
+    }
+}
+
+fn run_file(path: &Path) -> Result<(), Failure> {
+    let source = fs::read_to_string(path).expect("test file should be readable");
+    let expected = Expectations::parse(&source);
+    let result = Command::new(env!("CARGO_BIN_EXE_blox"))
+        .arg(path)
+        .args(flags(path))
+        .output()
+        .expect("blox binary should run");
+
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    let output: Vec<&str> = stdout.lines().collect();
+    let errors: Vec<&str> = stderr.lines().filter(|line| !line.is_empty()).collect();
+
+    let mut messages = vec![];
+    check_output(&expected.output, &output, &mut messages);
+    check_errors(&expected, &errors, &mut messages);
+
+    let exit_code = result.status.code().unwrap_or(-1);
+    if exit_code != expected.exit_code() {
+        messages.push(format!(
+            "Expected exit code {} and got {}.",
+            expected.exit_code(),
+            exit_code
+        ));
+    }
+
+    if messages.is_empty() {
+        Ok(())
+    } else {
+        Err(Failure {
+            path: path.to_path_buf(),
+            messages,
+        })
+    }
+}
+
+fn flags(path: &Path) -> Vec<&'static str> {
+    DIRECTORY_FLAGS
+        .iter()
+        .filter(|(dir, _)| path.components().any(|c| c.as_os_str() == *dir))
+        .flat_map(|(_, flags)| flags.iter().copied())
+        .collect()
+}
+
+fn check_output(expected: &[String], actual: &[&str], messages: &mut Vec<String>) {
+    for (index, line) in actual.iter().enumerate() {
+        match expected.get(index) {
+            Some(expected) if expected == line => (),
+            Some(expected) => messages.push(format!(
+                "Expected output '{}' and got '{}'.",
+                expected, line
+            )),
+            None => messages.push(format!("Got output '{}' when none was expected.", line)),
+        }
+    }
+
+    for line in expected.iter().skip(actual.len()) {
+        messages.push(format!("Missing expected output '{}'.", line));
+    }
+}
+
+fn check_errors(expected: &Expectations, actual: &[&str], messages: &mut Vec<String>) {
+    for warning in expected.warnings.iter() {
+        if !actual.contains(&warning.as_str()) {
+            messages.push(format!("Missing expected warning '{}'.", warning));
+        }
+    }
+
+    let actual: Vec<&str> = actual
+        .iter()
+        .copied()
+        .filter(|line| !expected.warnings.iter().any(|warning| warning == line))
+        .collect();
+
+    if let Some((message, line)) = &expected.runtime_error {
+        // Compile errors in a module come before the error importing it fails
+        // with.
+        for error in expected.compile_errors.iter() {
+            if !actual.contains(&error.as_str()) {
+                messages.push(format!("Missing expected error '{}'.", error));
+            }
+        }
+
+        let actual: Vec<&str> = actual
+            .iter()
+            .copied()
+            .filter(|line| !expected.compile_errors.iter().any(|error| error == line))
+            .collect();
+        match actual.first() {
+            Some(first) if first == message => (),
+            Some(first) => messages.push(format!(
+                "Expected runtime error '{}' and got '{}'.",
+                message, first
+            )),
+            None => messages.push(format!(
+                "Expected runtime error '{}' and got none.",
+                message
+            )),
+        }
+
+        let trace = format!("[line {}]", line);
+        if !actual.iter().skip(1).any(|error| error.starts_with(&trace)) {
+            messages.push(format!(
+                "Expected runtime error on line {} and got '{}'.",
+                line,
+                actual.get(1..).unwrap_or_default().join(" | ")
+            ));
+        }
+
+        return;
+    }
+
+    for error in actual.iter() {
+        if !expected
+            .compile_errors
+            .iter()
+            .any(|expected| expected == error)
+        {
+            messages.push(format!("Unexpected error '{}'.", error));
+        }
+    }
+
+    for error in expected.compile_errors.iter() {
+        if !actual.contains(&error.as_str()) {
+            messages.push(format!("Missing expected error '{}'.", error));
+        }
+    }
+}
+
+fn lox_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)
+        .expect("test directory should be readable")
+        .map(|entry| entry.expect("test directory entry").path())
+        .collect();
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            if !path.ends_with(MODULE_DIRECTORY) {
+                lox_files(&path, files);
+            }
+        } else if path.extension().is_some_and(|ext| ext == "lox") {
+            files.push(path);
+        }
+    }
+}
+
+#[test]
+fn conformance() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("lox");
+    let mut files = vec![];
+    lox_files(&root, &mut files);
+
+    let failures: Vec<Failure> = files
+        .iter()
+        .filter_map(|path| run_file(path).err())
+        .collect();
+
+    for failure in failures.iter() {
+        eprint!("{}", failure);
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} of {} conformance tests failed",
+        failures.len(),
+        files.len()
+    );
+}